@@ -0,0 +1,46 @@
+//! A rough generation throughput benchmark, run with `cargo run --release --example bench`.
+//! Generation is driven by the cheap `SmallRng` from a fixed set of seeds, so the numbers
+//! mostly reflect the cost of walking the chain and stay comparable between runs.
+extern crate markov;
+extern crate rand;
+
+use markov::Chain;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+/// Builds a chain of the given order from `sentences` random sentences over `vocabulary` words.
+fn build(order: usize, sentences: usize, vocabulary: usize) -> Chain<usize> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut chain = Chain::of_order(order);
+    for _ in 0..sentences {
+        let len = rng.gen_range(5..20);
+        chain.feed(
+            (0..len)
+                .map(|_| rng.gen_range(0..vocabulary))
+                .collect::<Vec<_>>(),
+        );
+    }
+    chain
+}
+
+fn bench(name: &str, chain: &Chain<usize>) {
+    let seeds = (0..1000).collect::<Vec<u64>>();
+    let start = Instant::now();
+    let outputs = chain.generate_for_seeds_with::<SmallRng>(&seeds);
+    let elapsed = start.elapsed();
+    let tokens = outputs.iter().map(Vec::len).sum::<usize>();
+    println!(
+        "{}: {} sentences, {} tokens in {:?} ({:.0} tokens/s)",
+        name,
+        outputs.len(),
+        tokens,
+        elapsed,
+        tokens as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    bench("small chain", &build(1, 100, 50));
+    bench("large chain", &build(2, 20_000, 5_000));
+}
@@ -32,22 +32,28 @@ extern crate serde_derive;
 extern crate serde_yaml;
 
 use std::borrow::ToOwned;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
-use std::fs::File;
-use std::hash::Hash;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
-use std::io::{BufReader, Result};
-#[cfg(feature = "yaml")]
-use std::io::{Error, ErrorKind};
+use std::io::{BufReader, Error, ErrorKind, Result};
 use std::iter::Map;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{SendError, SyncSender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
 #[cfg(feature = "graph")]
 use itertools::Itertools;
 #[cfg(feature = "graph")]
 use petgraph::graph::Graph;
-use rand::{thread_rng, Rng};
+use rand::rngs::{StdRng, ThreadRng};
+use rand::{thread_rng, Rng, RngCore, SeedableRng};
 #[cfg(feature = "yaml")]
 use serde::de::DeserializeOwned;
 #[cfg(feature = "yaml")]
@@ -61,15 +67,223 @@ impl<T> Chainable for T where T: Eq + Hash + Clone {}
 
 type Token<T> = Option<T>;
 
+/// The transitions of a chain: the number of times each token followed each state.
+type Transitions<T> = HashMap<Vec<Token<T>>, HashMap<Token<T>, f64>>;
+
+/// The transitions of a chain in reverse: every state that leads into each state, along with the
+/// number of times it did.
+type Predecessors<T> = HashMap<Vec<Token<T>>, Vec<(Vec<Token<T>>, f64)>>;
+
+/// The sequences fed with each id by `Chain::feed_tagged`: the tokens each was fed as, wrapped
+/// in sentence boundaries, along with the weight it still carries in the chain.
+type Tagged<T> = HashMap<u64, Vec<(Vec<Token<T>>, f64)>>;
+
 /// A generic [Markov chain](https://en.wikipedia.org/wiki/Markov_chain) for almost any type.
 /// In particular, elements of the chain must be `Eq`, `Hash`, and `Clone`.
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Chain<T>
 where
     T: Chainable,
 {
-    map: HashMap<Vec<Token<T>>, HashMap<Token<T>, usize>>,
+    map: Transitions<T>,
     order: usize,
+    /// When present, the number of times each original surface form was seen for each token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    surface_forms: Option<HashMap<T, HashMap<T, usize>>>,
+    /// When present, the bookkeeping needed to keep the chain within a memory budget.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    streaming: Option<Streaming<T>>,
+    /// When present, the statistics of every lower order recorded by `feed_with_order_weights`,
+    /// starting with those of order one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lower_orders: Option<Vec<Transitions<T>>>,
+    /// When present, how many times sequences of each length were fed into the chain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lengths: Option<BTreeMap<usize, f64>>,
+    /// When present, for each token that began a sequence fed into the chain, the total weight of
+    /// those sequences and the sum of their lengths, each counted with that same weight.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    openings: Option<HashMap<T, (f64, f64)>>,
+    /// When present, the sequences fed with each id by `feed_tagged`, so that they can be removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tagged: Option<Tagged<T>>,
+    /// When present, the canonical token that each alias is fed as.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aliases: Option<HashMap<T, T>>,
+    /// What generation does when it reaches a state without successors.
+    #[serde(default, skip_serializing_if = "DeadEndPolicy::is_default")]
+    dead_end_policy: DeadEndPolicy,
+    /// How scoring treats tokens the chain has never seen.
+    #[serde(default, skip_serializing_if = "UnknownTokenPolicy::is_default")]
+    unknown_token_policy: UnknownTokenPolicy,
+    /// When present, the token that stands in for unseen tokens when scoring under `UnkState`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unknown_token: Option<T>,
+    /// The random number generator used for generation, once the chain is seeded.
+    #[serde(skip)]
+    rng: ChainRng,
+}
+
+/// What generation does when it reaches a dead end: a state other than the end of a sentence
+/// that has no successors, such as one left behind by evicting or removing transitions. States
+/// listed by `Chain::absorbing_states`, which generation could otherwise never leave, are handled
+/// as dead ends as well, once a walk has run longer than the chain has states.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum DeadEndPolicy {
+    /// Generation stops, returning what it emitted so far.
+    #[default]
+    Terminate,
+    /// Generation carries on from the beginning of a new sentence.
+    RestartFromStart,
+    /// Generation carries on from a state picked uniformly at random among those with successors.
+    JumpRandom,
+}
+
+impl DeadEndPolicy {
+    fn is_default(&self) -> bool {
+        *self == DeadEndPolicy::default()
+    }
+}
+
+/// How scoring a sequence, such as with `sequence_probability` or `perplexity`, treats tokens
+/// that the chain has never seen. Before a token is judged unseen, it is resolved the way feeding
+/// has resolved those of the chain: an alias stands for its canonical token, and in a chain that
+/// tracks surface forms, such as a case-insensitive one, any form a token was seen in stands for
+/// the token itself.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum UnknownTokenPolicy {
+    /// Unseen tokens are scored like any other, so without smoothing, a sequence containing one
+    /// has a probability of zero and makes the perplexity infinite.
+    #[default]
+    ZeroProb,
+    /// Unseen tokens are scored as the token reserved with `Chain::reserve_unknown_token`, which
+    /// the chain should have been trained with in place of rare tokens, so that it has learned
+    /// how unknown tokens behave. Without a reserved token, this is the same as `ZeroProb`.
+    UnkState,
+    /// Unseen tokens are left out of the sequence before scoring it, so the rest is scored as if
+    /// they had never been there, and they do not count towards the perplexity. This overstates
+    /// how well the chain fits text with many unseen tokens.
+    Skip,
+}
+
+impl UnknownTokenPolicy {
+    fn is_default(&self) -> bool {
+        *self == UnknownTokenPolicy::default()
+    }
+}
+
+/// The random number generator of a seeded chain. This is not part of the identity of a chain, so
+/// it is ignored when comparing chains.
+#[derive(Default)]
+struct ChainRng(Option<Mutex<StdRng>>);
+
+impl Clone for ChainRng {
+    fn clone(&self) -> Self {
+        ChainRng(self.0.as_ref().map(|rng| Mutex::new(lock(rng).clone())))
+    }
+}
+
+impl PartialEq for ChainRng {
+    fn eq(&self, _: &ChainRng) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for ChainRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "Seeded"
+        } else {
+            "Unseeded"
+        })
+    }
+}
+
+impl ChainRng {
+    /// Gets the random number generator to use for a single generation. This holds on to the
+    /// generator of a seeded chain until it is dropped.
+    fn handle(&self) -> RngHandle<'_> {
+        match self.0 {
+            Some(ref rng) => RngHandle::Seeded(lock(rng)),
+            None => RngHandle::Thread(thread_rng()),
+        }
+    }
+}
+
+/// The position of the random number generator of a chain, as taken by `Chain::rng_state`, for
+/// rewinding the chain to it with `Chain::restore_rng`.
+#[derive(Clone, Debug)]
+pub struct RngSnapshot(Option<StdRng>);
+
+/// The random number generator to use for a single generation: either that of a seeded chain, or
+/// the thread-local one.
+enum RngHandle<'a> {
+    Seeded(MutexGuard<'a, StdRng>),
+    Thread(ThreadRng),
+}
+
+impl<'a> RngCore for RngHandle<'a> {
+    fn next_u32(&mut self) -> u32 {
+        match *self {
+            RngHandle::Seeded(ref mut rng) => rng.next_u32(),
+            RngHandle::Thread(ref mut rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match *self {
+            RngHandle::Seeded(ref mut rng) => rng.next_u64(),
+            RngHandle::Thread(ref mut rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match *self {
+            RngHandle::Seeded(ref mut rng) => rng.fill_bytes(dest),
+            RngHandle::Thread(ref mut rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> ::std::result::Result<(), rand::Error> {
+        match *self {
+            RngHandle::Seeded(ref mut rng) => rng.try_fill_bytes(dest),
+            RngHandle::Thread(ref mut rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+/// Locks a mutex, carrying on even if another thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The bookkeeping for a chain in streaming mode, recording when each transition was last
+/// reinforced so that the least recently reinforced ones can be evicted first. Every transition of
+/// the chain has exactly one stamp, so the number of stamps doubles as a running count of them.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct Streaming<T>
+where
+    T: Chainable,
+{
+    max_transitions: usize,
+    clock: u64,
+    stamps: HashMap<(Vec<Token<T>>, Token<T>), u64>,
+    recency: BTreeMap<u64, (Vec<Token<T>>, Token<T>)>,
+}
+
+impl<T> Streaming<T>
+where
+    T: Chainable,
+{
+    /// Records that a transition has just been reinforced.
+    fn touch(&mut self, state: &[Token<T>], next: &Token<T>) {
+        self.clock += 1;
+        let key = (state.to_vec(), next.clone());
+        if let Some(old) = self.stamps.insert(key.clone(), self.clock) {
+            self.recency.remove(&old);
+        }
+        self.recency.insert(self.clock, key);
+    }
 }
 
 impl<T> Default for Chain<T>
@@ -94,6 +308,10 @@ where
     /// tokens to use for each mapping in the chain. Higher orders mean that the generated text
     /// will more closely resemble the training set. Increasing the order can yield more realistic
     /// output, but typically at the cost of requiring more training data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is zero.
     pub fn of_order(order: usize) -> Chain<T> {
         assert!(order != 0);
         Chain {
@@ -103,500 +321,6410 @@ where
                 map
             },
             order,
+            surface_forms: None,
+            streaming: None,
+            lower_orders: None,
+            lengths: None,
+            openings: None,
+            tagged: None,
+            aliases: None,
+            dead_end_policy: DeadEndPolicy::default(),
+            unknown_token_policy: UnknownTokenPolicy::default(),
+            unknown_token: None,
+            rng: ChainRng::default(),
+        }
+    }
+
+    /// Builds a first-order chain directly from a map of transitions computed elsewhere, in which
+    /// `map[a][b]` is the weight of the transition from `a` to `b`. The beginning and end of a
+    /// sentence are given as the tokens `start` and `end`, which are only used as markers and are
+    /// never generated. Transitions into `start` or out of `end` have no meaning and are dropped,
+    /// as are those going straight from `start` to `end`. This returns an error, and builds
+    /// nothing, if `start` has no transitions in the map or if no transition leads to `end`,
+    /// since the chain could then not begin or not end a sentence.
+    pub fn from_map(
+        map: HashMap<T, HashMap<T, f64>>,
+        start: T,
+        end: T,
+    ) -> ::std::result::Result<Chain<T>, String> {
+        if !map.contains_key(&start) {
+            return Err("the map has no transitions out of the start token".to_owned());
+        }
+        if !map.values().any(|nexts| nexts.contains_key(&end)) {
+            return Err("the map has no transitions into the end token".to_owned());
+        }
+        let boundary = |token: T, sentinel: &T| {
+            if token == *sentinel {
+                None
+            } else {
+                Some(token)
+            }
+        };
+        let mut chain = Chain::new();
+        for (from, nexts) in map {
+            if from == end {
+                continue;
+            }
+            let state = vec![boundary(from, &start)];
+            for (to, count) in nexts {
+                if to == start || (state[0].is_none() && to == end) {
+                    continue;
+                }
+                chain.reinforce(&state, &boundary(to, &end), count);
+            }
         }
+        Ok(chain)
+    }
+
+    /// Returns the order of the chain, which is the number of previous tokens used for each
+    /// mapping in the chain. A context used to seed generation should be this many tokens long.
+    /// The order is part of the saved form of a chain, so loaded chains keep theirs.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// Seeds the chain with its own random number generator, which is then used by every later
+    /// generation from the chain instead of the thread-local one. This makes generation
+    /// reproducible: after seeding a chain with the same seed, it generates the same outputs in
+    /// the same order. That holds for the chain and its clones, but not for separately built or
+    /// loaded copies, as those may consider successors in a different order. Chains that are never
+    /// seeded keep using the thread-local random number generator, and the seed is not saved along
+    /// with the chain.
+    pub fn seed(&mut self, seed: u64) -> &mut Chain<T> {
+        self.rng = ChainRng(Some(Mutex::new(StdRng::seed_from_u64(seed))));
+        self
+    }
+
+    /// Takes a snapshot of the position of the random number generator of a seeded chain, so that
+    /// the chain can later be rewound to it with `restore_rng` to replay the same generations,
+    /// such as to reproduce a problematic output in the middle of a run. This only makes sense
+    /// for a chain seeded with `seed`: the thread-local random number generator of an unseeded
+    /// chain cannot be captured, so its snapshot holds nothing.
+    pub fn rng_state(&self) -> RngSnapshot {
+        RngSnapshot(self.rng.0.as_ref().map(|rng| lock(rng).clone()))
+    }
+
+    /// Rewinds the random number generator of the chain to a snapshot taken with `rng_state`, so
+    /// that the generations that followed the snapshot are generated again, as long as the chain
+    /// has not changed in the meantime. Restoring a snapshot taken from an unseeded chain leaves
+    /// the chain unseeded, using the thread-local random number generator.
+    pub fn restore_rng(&mut self, snap: RngSnapshot) -> &mut Chain<T> {
+        self.rng = ChainRng(snap.0.map(Mutex::new));
+        self
+    }
+
+    /// Sets what generation does when it reaches a dead end, which is to terminate by default.
+    /// The other policies keep generation going, so the output may then join up pieces that
+    /// never followed each other in the training data. So that generation still ends on a chain
+    /// riddled with dead ends, it recovers from at most as many of them as the chain has states,
+    /// and terminates at the next one. The policy applies to `generate` and its variants that
+    /// sample a single walk through the chain, but not to those that search it, such as
+    /// `generate_between`, nor to `random_walk`, which always restarts, or to `advance`.
+    pub fn set_deadend_policy(&mut self, policy: DeadEndPolicy) -> &mut Chain<T> {
+        self.dead_end_policy = policy;
+        self
+    }
+
+    /// Sets how scoring sequences treats tokens the chain has never seen, which is to give them a
+    /// probability of zero by default. The policy applies to `sequence_probability`,
+    /// `sequence_log_probability`, `perplexity`, and `smoothed_perplexity`.
+    pub fn set_unknown_token_policy(&mut self, policy: UnknownTokenPolicy) -> &mut Chain<T> {
+        self.unknown_token_policy = policy;
+        self
+    }
+
+    /// Reserves a token to stand in for unseen tokens when scoring sequences under
+    /// `UnknownTokenPolicy::UnkState`, such as `"<UNK>"`. For the policy to be of any use, the
+    /// chain should be trained with this token in place of some rare tokens, which is up to the
+    /// caller.
+    pub fn reserve_unknown_token(&mut self, token: T) -> &mut Chain<T> {
+        self.unknown_token = Some(token);
+        self
     }
 
     /// Determines whether or not the chain is empty. A chain is considered empty if nothing has
-    /// been fed into it.
+    /// been fed into it, or rather, if nothing it was fed can begin a sentence: a chain fed only
+    /// with a weight of zero is empty too.
     pub fn is_empty(&self) -> bool {
-        self.map[&vec![None; self.order]].is_empty()
+        self.map
+            .get(&vec![None; self.order])
+            .is_none_or(|nexts| !nexts.values().any(|&count| count > 0.0))
+    }
+
+    /// Determines whether or not the chain knows the given token, that is, whether it has been fed
+    /// into the chain at any point.
+    pub fn contains(&self, token: &T) -> bool {
+        let token = Some(token.clone());
+        self.map
+            .iter()
+            .any(|(state, nexts)| state.contains(&token) || nexts.contains_key(&token))
+    }
+
+    /// Computes the fraction of the given words that the chain knows, in the sense of `contains`,
+    /// as a number between zero and one. Duplicates count as often as they appear. This helps
+    /// judge whether a chain was trained on enough of the vocabulary of a domain. Since none of an
+    /// empty list of words is missing, its coverage is one.
+    pub fn vocabulary_coverage(&self, words: &[T]) -> f64 {
+        if words.is_empty() {
+            return 1.0;
+        }
+        let known = self.tokens().into_iter().collect::<HashSet<_>>();
+        let covered = words.iter().filter(|word| known.contains(word)).count();
+        covered as f64 / words.len() as f64
+    }
+
+    /// Gets the weight of the transition from `from` to `to`, which is the number of times `to` was
+    /// seen directly following `from`. This is a whole number unless the chain was fed with
+    /// fractional weights, such as through `feed_weighted` or `feed_with_decay`. In chains of
+    /// higher order, this sums the weights over every state ending with `from`.
+    pub fn weight(&self, from: &T, to: &T) -> f64 {
+        self.successors(from)
+            .get(&Some(to.clone()))
+            .cloned()
+            .unwrap_or(0.0)
+    }
+
+    /// Counts the number of times `to` was seen directly following `from`. This is the weight of
+    /// the transition, rounded to the nearest whole number, for the common case of chains fed
+    /// only with whole counts.
+    pub fn count(&self, from: &T, to: &T) -> usize {
+        self.weight(from, to).round() as usize
+    }
+
+    /// Computes the surprisal of the transition from `from` to `to`, which is its information
+    /// content in bits: `-log2(p)`, where `p` is the probability that `to` follows `from`. Likely
+    /// transitions have a surprisal near zero, and each halving of the probability adds a bit. A
+    /// transition that was never seen has an infinite surprisal, and so does any transition from
+    /// a token that was never seen. This is the per-step counterpart of the entropies reported by
+    /// `generate_with_step_entropy`, which are the expected surprisal of each step. In chains of
+    /// higher order, this is computed over the successors of every state ending with `from`.
+    pub fn surprisal(&self, from: &T, to: &T) -> f64 {
+        let successors = self.successors(from);
+        let total = successors
+            .values()
+            .filter(|&&count| count > 0.0)
+            .sum::<f64>();
+        let count = successors.get(&Some(to.clone())).cloned().unwrap_or(0.0);
+        if count > 0.0 {
+            -(count / total).log2()
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    /// Determines whether or not the chain has ever seen `to` directly follow `from`. In chains of
+    /// higher order, this is the case if any state ending with `from` has `to` as a successor.
+    pub fn has_transition(&self, from: &T, to: &T) -> bool {
+        self.successors(from)
+            .get(&Some(to.clone()))
+            .is_some_and(|&count| count > 0.0)
+    }
+
+    /// Checks the internal invariants of the chain, for tracking down corruption from a chain put
+    /// together by hand, such as with `from_map`, `add_counts`, or deserialization. This checks
+    /// that the beginning of a sentence is a state, that every state has `order` tokens, that no
+    /// state continues past the end of a sentence, that every count is finite and non-negative,
+    /// and that every successor leads to a state the chain knows. Counts are never cached, so
+    /// there are no sums to check against them. Returns a description of the first violation
+    /// found, if any. A chain in streaming mode or fed with `feed_skipgram` may legitimately have
+    /// successors that lead nowhere, which generation then handles with the dead-end policy.
+    pub fn validate(&self) -> ::std::result::Result<(), String> {
+        if !self.map.contains_key(&vec![None; self.order]) {
+            return Err("the chain has no state for the beginning of a sentence".to_owned());
+        }
+        for (state, nexts) in &self.map {
+            if state.len() != self.order {
+                return Err(format!(
+                    "a state has {} tokens in a chain of order {}",
+                    state.len(),
+                    self.order
+                ));
+            }
+            if continues_past_end(state) {
+                return Err("a state continues past the end of a sentence".to_owned());
+            }
+            for (next, &count) in nexts {
+                if !count.is_finite() || count < 0.0 {
+                    return Err(format!("a transition has an invalid count of {}", count));
+                }
+                if next.is_some() && !self.map.contains_key(&next_state(state, next)) {
+                    return Err("a transition leads to a state the chain does not know".to_owned());
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Feeds the chain a collection of tokens. This operation is `O(n)` where `n` is the number of
     /// tokens to be fed into the chain.
     pub fn feed<S: AsRef<[T]>>(&mut self, tokens: S) -> &mut Chain<T> {
+        self.feed_weighted(tokens, 1.0)
+    }
+
+    /// Feeds the chain a collection of tokens with the given weight. Feeding a sequence normally
+    /// counts each of its transitions once, while this counts each of them `weight` times, which
+    /// need not be a whole number. A sequence fed with a weight of `0.5` thus has half the
+    /// influence of one fed normally.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is negative or not finite.
+    pub fn feed_weighted<S: AsRef<[T]>>(&mut self, tokens: S, weight: f64) -> &mut Chain<T> {
+        assert!(weight >= 0.0 && weight.is_finite());
         let tokens = tokens.as_ref();
         if tokens.is_empty() {
             return self;
         }
-        let mut toks = vec![None; self.order];
-        toks.extend(tokens.iter().map(|token| Some(token.clone())));
-        toks.push(None);
+        *self
+            .lengths
+            .get_or_insert_with(BTreeMap::new)
+            .entry(tokens.len())
+            .or_insert(0.0) += weight;
+        let toks = self.wrap(tokens);
+        if let Some(Some(ref first)) = toks.get(self.order) {
+            let opening = self
+                .openings
+                .get_or_insert_with(HashMap::new)
+                .entry(first.clone())
+                .or_insert((0.0, 0.0));
+            opening.0 += weight;
+            opening.1 += weight * tokens.len() as f64;
+        }
         for p in toks.windows(self.order + 1) {
-            self.map
-                .entry(p[0..self.order].to_vec())
-                .or_insert_with(HashMap::new);
-            self.map
-                .get_mut(&p[0..self.order].to_vec())
-                .unwrap()
-                .add(p[self.order].clone(), 1);
+            self.reinforce(&p[0..self.order], &p[self.order], weight);
         }
+        self.evict();
         self
     }
 
-    /// Generates a collection of tokens from the chain. This operation is `O(mn)` where `m` is the
-    /// length of the generated collection, and `n` is the number of possible states from a given
-    /// state.
-    pub fn generate(&self) -> Vec<T> {
-        let mut ret = Vec::new();
-        let mut curs = vec![None; self.order];
-        loop {
-            let next = self.map[&curs].next();
-            curs = curs[1..self.order].to_vec();
-            curs.push(next.clone());
-            if let Some(next) = next {
-                ret.push(next)
-            };
-            if curs[self.order - 1].is_none() {
-                break;
+    /// Feeds the chain every sentence yielded by an iterator, one at a time as it is produced,
+    /// exactly as if each were passed to `feed` in turn. Since the sentences are never collected,
+    /// this can ingest a corpus far larger than memory from a lazy source, such as a parser that
+    /// yields one sentence at a time.
+    pub fn feed_sentences_iter<I>(&mut self, sentences: I) -> &mut Chain<T>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[T]>,
+    {
+        for sentence in sentences {
+            self.feed(sentence);
+        }
+        self
+    }
+
+    /// Feeds the chain a long collection of tokens split into consecutive chunks of at most
+    /// `chunk_len` tokens, each fed as its own sequence as if passed to `feed`, so that a long
+    /// document yields many sentences that can recombine rather than a single enormous one. The
+    /// chunks do not overlap, so the transitions across the boundary between two chunks are not
+    /// recorded, and each chunk instead gets its own beginning and end. The first `order` tokens
+    /// of every chunk are therefore learned only as the beginning of a sentence, so `chunk_len`
+    /// should be well above the order of the chain for the chunks to teach it much else. Only the
+    /// last chunk may be shorter than `chunk_len`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_len` is zero.
+    pub fn feed_chunked<S: AsRef<[T]>>(&mut self, tokens: S, chunk_len: usize) -> &mut Chain<T> {
+        assert!(chunk_len != 0);
+        for chunk in tokens.as_ref().chunks(chunk_len) {
+            self.feed(chunk);
+        }
+        self
+    }
+
+    /// Feeds the chain the skip-grams of a collection of tokens, so that it learns which tokens
+    /// tend to follow `skip` positions further along, such as the `C` in `A B C` for a skip of one.
+    /// The tokens are surrounded by the sentence boundaries like in `feed`, and each state of
+    /// `order` tokens, including those at the beginning of the sentence, is followed by the token
+    /// `skip` positions past the one right after it, which may be the end of the sentence. Only
+    /// these transitions are recorded, so a skip of zero records the same ones as `feed`, and
+    /// adjacent transitions can be added by feeding the tokens with `feed` as well. Sequences that
+    /// are too short to have any skip-grams are not fed at all.
+    pub fn feed_skipgram<S: AsRef<[T]>>(&mut self, tokens: S, skip: usize) -> &mut Chain<T> {
+        let tokens = tokens.as_ref();
+        if tokens.is_empty() {
+            return self;
+        }
+        let toks = self.wrap(tokens);
+        for p in toks.windows(self.order + 1 + skip) {
+            self.reinforce(&p[0..self.order], &p[self.order + skip], 1.0);
+        }
+        self.evict();
+        self
+    }
+
+    /// Works out what feeding a collection of tokens would change, without feeding it: how many
+    /// states and transitions the chain would gain. A transition that the chain already knows and
+    /// would merely reinforce is not new, and neither is a state or transition that occurs more
+    /// than once in the tokens beyond its first occurrence. The chain is left untouched, so this
+    /// can preview the effect of an ingest before going ahead with it.
+    pub fn feed_preview(&self, tokens: &[T]) -> FeedStats {
+        if tokens.is_empty() {
+            return FeedStats::default();
+        }
+        let toks = self.wrap(tokens);
+        let mut states = HashSet::new();
+        let mut transitions = HashSet::new();
+        for p in toks.windows(self.order + 1) {
+            let (state, next) = (&p[0..self.order], &p[self.order]);
+            match self.map.get(state) {
+                Some(nexts) if nexts.contains_key(next) => {}
+                Some(_) => {
+                    transitions.insert((state, next));
+                }
+                None => {
+                    states.insert(state);
+                    transitions.insert((state, next));
+                }
             }
         }
-        ret
+        FeedStats {
+            new_states: states.len(),
+            new_transitions: transitions.len(),
+        }
     }
 
-    /// Generates a collection of tokens from the chain, starting with the given token. This
-    /// operation is O(mn) where m is the length of the generated collection, and n is the number
-    /// of possible states from a given state. This returns an empty vector if the token is not
-    /// found.
-    pub fn generate_from_token(&self, token: T) -> Vec<T> {
-        let mut curs = vec![None; self.order - 1];
-        curs.push(Some(token.clone()));
-        if !self.map.contains_key(&curs) {
+    /// Feeds the chain a collection of tokens after collapsing every run of consecutive identical
+    /// tokens into a single one, so that `"very very good"` is fed as `"very good"`. This keeps
+    /// stuttering in noisy data from becoming self-loops that make the output stutter too. Note
+    /// that it removes deliberate repetition just the same, such as emphasis, so the chain can then
+    /// never generate it.
+    pub fn feed_collapse_repeats<S: AsRef<[T]>>(&mut self, tokens: S) -> &mut Chain<T> {
+        let mut collapsed = tokens.as_ref().to_vec();
+        collapsed.dedup();
+        self.feed(collapsed)
+    }
+
+    /// Feeds the chain a collection of tokens just like `feed`, returning the tokens as they were
+    /// actually fed: preceded by as many sentence starts as the order of the chain and followed by
+    /// a sentence end, both represented by `None`. Each window of `order + 1` consecutive tokens
+    /// in the result is one transition that was reinforced. This is meant for checking that a
+    /// tokenizer produces what is expected. Since empty collections are not fed at all, feeding
+    /// one returns an empty collection.
+    pub fn feed_debug<S: AsRef<[T]>>(&mut self, tokens: S) -> Vec<Option<T>> {
+        let tokens = tokens.as_ref();
+        if tokens.is_empty() {
             return Vec::new();
         }
-        let mut ret = vec![token];
-        loop {
-            let next = self.map[&curs].next();
-            curs = curs[1..self.order].to_vec();
-            curs.push(next.clone());
-            if let Some(next) = next {
-                ret.push(next)
-            };
-            if curs[self.order - 1].is_none() {
-                break;
+        self.feed(tokens);
+        self.wrap(tokens)
+    }
+
+    /// Feeds the chain a collection of tokens just like `feed`, remembering them under the given
+    /// id so that they can later be taken back out with `remove_tagged`. Several sequences can be
+    /// fed with the same id, and are then removed together. The sequences are kept along with the
+    /// chain until they are removed, so this costs memory in proportion to their length.
+    pub fn feed_tagged<S: AsRef<[T]>>(&mut self, id: u64, tokens: S) -> &mut Chain<T> {
+        let tokens = tokens.as_ref();
+        if tokens.is_empty() {
+            return self;
+        }
+        let toks = self.wrap(tokens);
+        self.tagged
+            .get_or_insert_with(HashMap::new)
+            .entry(id)
+            .or_default()
+            .push((toks, 1.0));
+        self.feed(tokens)
+    }
+
+    /// Removes everything fed into the chain with the given id through `feed_tagged`, as if those
+    /// sequences had never been fed. Each of their transitions is weakened by exactly the weight
+    /// the sequence still carries, which is one unless `feed_with_decay` has faded it since, and
+    /// is forgotten entirely once nothing else supports it. The sequences are taken out as the
+    /// tokens they were fed as, so aliases added in the meantime do not change what is removed.
+    /// States from which the end of a sentence can then no longer be reached are removed as well.
+    /// Transitions that have since been evicted or filtered out of the chain are skipped. Nothing
+    /// happens if no sequences were fed with the id.
+    pub fn remove_tagged(&mut self, id: u64) -> &mut Chain<T> {
+        let sequences = match self.tagged.as_mut().and_then(|tagged| tagged.remove(&id)) {
+            Some(sequences) => sequences,
+            None => return self,
+        };
+        for (toks, weight) in sequences {
+            let len = toks.len() - self.order - 1;
+            if let Some(count) = self
+                .lengths
+                .as_mut()
+                .and_then(|lengths| lengths.get_mut(&len))
+            {
+                *count -= weight;
+            }
+            let first = toks[self.order].clone();
+            if let Some(opening) = self
+                .openings
+                .as_mut()
+                .and_then(|openings| openings.get_mut(first.as_ref()?))
+            {
+                opening.0 -= weight;
+                opening.1 -= weight * len as f64;
+            }
+            for p in toks.windows(self.order + 1) {
+                self.weaken(&p[0..self.order], &p[self.order], weight);
             }
         }
-        ret
+        if let Some(ref mut lengths) = self.lengths {
+            lengths.retain(|_, &mut count| count > 1e-9);
+        }
+        if let Some(ref mut openings) = self.openings {
+            openings.retain(|_, &mut (weight, _)| weight > 1e-9);
+        }
+        self.prune_dead_ends();
+        self
     }
 
-    /// Generates a collection of tokens from the chain, starting with the given tokens.
-    pub fn generate_from_tokens(&self, tokens: Vec<T>) -> Vec<T> {
-        let mut curs = vec![None; self.order - 1];
-        for token in &tokens {
-            curs.push(Some(token.clone()));
+    /// Feeds the chain a collection of tokens, first fading out everything it learned before. All
+    /// of the existing counts are multiplied by `decay`, which must be between zero and one, and
+    /// then the tokens are fed as usual. Repeatedly feeding the chain this way implements
+    /// exponential forgetting for training on data that drifts over time: the older a
+    /// sequence, the less it counts. A `decay` of one is the same as `feed`, and a decay of zero
+    /// forgets everything but the new sequence. This makes the counts of the chain fractional.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `decay` is not between zero and one, inclusive.
+    pub fn feed_with_decay<S: AsRef<[T]>>(&mut self, tokens: S, decay: f64) -> &mut Chain<T> {
+        assert!((0.0..=1.0).contains(&decay));
+        for nexts in self.map.values_mut() {
+            for count in nexts.values_mut() {
+                *count *= decay;
+            }
         }
-        curs = curs[1..self.order].to_vec();
-        if !self.map.contains_key(&curs) {
-            return Vec::new();
+        for count in self.lengths.iter_mut().flat_map(BTreeMap::values_mut) {
+            *count *= decay;
         }
-        let mut ret = tokens.clone();
-        loop {
-            let next = self.map[&curs].next();
-            curs = curs[1..self.order].to_vec();
-            curs.push(next.clone());
-            if let Some(next) = next {
-                ret.push(next)
-            };
-            if curs[self.order - 1].is_none() {
-                break;
+        for opening in self.openings.iter_mut().flat_map(HashMap::values_mut) {
+            opening.0 *= decay;
+            opening.1 *= decay;
+        }
+        for sequences in self.tagged.iter_mut().flat_map(HashMap::values_mut) {
+            for (_, weight) in sequences {
+                *weight *= decay;
             }
         }
-        ret
+        self.feed(tokens)
     }
 
-    /// Merges 2 chains (self and other) into self, consuming the other one. Both chains must be of
-    /// the same order. This method is useful when you want to speed up chain building - chains
-    /// built independently (e.g. in parallel with rayon) can be merged into a final one.
-    pub fn merge(&mut self, other: Chain<T>) -> &Chain<T> {
-        assert!(self.order == other.order);
+    /// Feeds the chain a collection of tokens, training every order up to that of the chain at
+    /// once. There must be one weight per order: `weights[i]` scales how strongly the statistics
+    /// of order `i + 1` are reinforced, so each transition of that order counts `weights[i]`
+    /// times, much like in `feed_weighted`. The last weight therefore applies to the chain itself,
+    /// while the statistics of the lower orders are kept alongside it, untouched by generation,
+    /// and can be retrieved as chains of their own with `lower_order_chain`. This builds every
+    /// component of an interpolated multi-order model in a single pass over the data. Only this
+    /// method records lower-order statistics; feeding the chain in any other way leaves them as
+    /// they are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of weights differs from the order of the chain, or if any of them is
+    /// negative or not finite, like the weight of `feed_weighted`.
+    pub fn feed_with_order_weights<S: AsRef<[T]>>(
+        &mut self,
+        tokens: S,
+        weights: &[f64],
+    ) -> &mut Chain<T> {
+        assert!(weights.len() == self.order);
+        assert!(weights
+            .iter()
+            .all(|&weight| weight >= 0.0 && weight.is_finite()));
+        let tokens = tokens.as_ref();
+        if tokens.is_empty() {
+            return self;
+        }
+        let order = self.order;
+        let toks = self.wrap(tokens);
+        let lower_orders = self
+            .lower_orders
+            .get_or_insert_with(|| vec![HashMap::new(); order - 1]);
+        for (i, (map, &weight)) in lower_orders.iter_mut().zip(weights).enumerate() {
+            let order = i + 1;
+            for p in toks[self.order - order..].windows(order + 1) {
+                map.entry(p[0..order].to_vec())
+                    .or_default()
+                    .add(p[order].clone(), weight);
+            }
+        }
+        self.feed_weighted(tokens, weights[self.order - 1])
+    }
 
-        for (tokens, next) in other.map {
-            let states = self.map.entry(tokens).or_insert_with(HashMap::new);
+    /// Builds a chain of the given order from the statistics of this one. For the order of this
+    /// chain, this is a copy of it. For lower orders, this is the chain made from the statistics
+    /// recorded by `feed_with_order_weights`, or `None` if the chain was never fed that way.
+    /// Higher orders are never available and neither is order zero.
+    pub fn lower_order_chain(&self, order: usize) -> Option<Chain<T>> {
+        if order == self.order {
+            return Some(self.clone());
+        }
+        let map = self.lower_orders.as_ref()?.get(order.checked_sub(1)?)?;
+        let mut chain = Chain::of_order(order);
+        chain.map.extend(map.clone());
+        chain.surface_forms = self.surface_forms.clone();
+        chain.lengths = self.lengths.clone();
+        chain.openings = self.openings.clone();
+        Some(chain)
+    }
 
-            for (token, count) in next {
-                states.add(token, count);
+    /// Makes `alias` a synonym of `canonical`, so that from now on, feeding `alias` into the chain
+    /// is the same as feeding `canonical` and their statistics are consolidated under the latter.
+    /// This applies to every method that feeds the chain. If `canonical` is itself an alias, the
+    /// token it stands for is used instead, and aliases of `alias` become aliases of `canonical`
+    /// too. Only what is fed afterwards is affected: if `alias` was already fed into the chain,
+    /// its existing states and transitions are kept apart as they were, and can be consolidated
+    /// with `map_states` if needed.
+    pub fn add_alias(&mut self, canonical: T, alias: T) -> &mut Chain<T> {
+        let canonical = self.canonical(&canonical);
+        let aliases = self.aliases.get_or_insert_with(HashMap::new);
+        if canonical == alias {
+            aliases.remove(&alias);
+            return self;
+        }
+        for other in aliases.values_mut() {
+            if *other == alias {
+                *other = canonical.clone();
             }
         }
+        aliases.insert(alias, canonical);
+        self
+    }
 
+    /// Feeds the chain pre-counted transitions, given as `(from, to, count)` triples. A `None`
+    /// token stands for a sentence boundary: `(None, Some(a), n)` records that `a` began a
+    /// sentence `n` times, and `(Some(a), None, n)` that `a` ended one `n` times. States are
+    /// created as needed and counts are added to any already present in the chain.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain is not of order one, since a single preceding token only describes a
+    /// state of a first-order chain.
+    pub fn feed_transitions<I>(&mut self, triples: I) -> &mut Chain<T>
+    where
+        I: IntoIterator<Item = (Option<T>, Option<T>, usize)>,
+    {
+        assert!(self.order == 1);
+        for (from, to, count) in triples {
+            let from = from.map(|token| self.canonical(&token));
+            let to = to.map(|token| self.canonical(&token));
+            self.reinforce(&[from], &to, count as f64);
+        }
+        self.evict();
         self
     }
 
-    /// Produces an infinite iterator of generated token collections.
-    pub fn iter(&self) -> InfiniteChainIterator<T> {
-        InfiniteChainIterator { chain: self }
+    /// Adds pre-counted transitions computed elsewhere into the chain, in which `counts[a][b]` is
+    /// the number of times `b` followed `a`. This is the counterpart of `merge` for statistics
+    /// that are not themselves in a chain, such as partial counts gathered incrementally. As in
+    /// `feed_transitions`, a `None` token stands for a sentence boundary, states are created as
+    /// needed, and overlapping transitions have their counts summed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain is not of order one, like `feed_transitions`.
+    pub fn add_counts(
+        &mut self,
+        counts: &HashMap<Option<T>, HashMap<Option<T>, usize>>,
+    ) -> &mut Chain<T> {
+        self.feed_transitions(counts.iter().flat_map(|(from, nexts)| {
+            nexts
+                .iter()
+                .map(move |(to, &count)| (from.clone(), to.clone(), count))
+        }))
     }
 
-    /// Produces an iterator for the specified number of generated token collections.
-    pub fn iter_for(&self, size: usize) -> SizedChainIterator<T> {
-        SizedChainIterator { chain: self, size }
+    /// Generates a collection of tokens from the chain. This operation is `O(mn)` where `m` is the
+    /// length of the generated collection, and `n` is the number of possible states from a given
+    /// state.
+    pub fn generate(&self) -> Vec<T> {
+        self.generate_with(&mut self.rng())
     }
 
-    /// Create a graph using `petgraph` from the markov chain.
-    #[cfg(feature = "graph")]
-    pub fn graph(&self) -> Graph<Vec<Token<T>>, f64> {
-        let mut graph = Graph::new();
+    /// Generates one collection of tokens from the chain for each of the given seeds, in the same
+    /// order. Each output is exactly what `generate` would produce first after seeding the chain
+    /// with that seed, which makes it easy to compare runs across experiments. The chain itself
+    /// is left untouched, whether it is seeded or not.
+    pub fn generate_for_seeds(&self, seeds: &[u64]) -> Vec<Vec<T>> {
+        self.generate_for_seeds_with::<StdRng>(seeds)
+    }
 
-        // Create all possible node and store indices into hashmap.
-        let state_map = self
-            .map
+    /// Generates one collection of tokens from the chain for each of the given seeds, just like
+    /// `generate_for_seeds`, but with a random number generator of your choosing seeded from each
+    /// seed. A cheap, non-cryptographic generator such as `rand::rngs::SmallRng` keeps the cost of
+    /// randomness out of the way when measuring the chain itself, and fixed seeds keep the results
+    /// comparable from run to run.
+    pub fn generate_for_seeds_with<R: Rng + SeedableRng>(&self, seeds: &[u64]) -> Vec<Vec<T>> {
+        seeds
             .iter()
-            .flat_map(|(state, nexts)| {
-                let mut states = vec![state.clone()];
-
-                let mut state = state.clone();
-                state.remove(0);
+            .map(|&seed| self.generate_with(&mut R::seed_from_u64(seed)))
+            .collect()
+    }
 
-                for next in nexts {
-                    let mut next_state = state.clone();
-                    next_state.push(next.0.clone());
-                    states.push(next_state);
+    /// Generates a collection of tokens by walking two chains in turn, with `a` picking every token
+    /// at an even position and `b` every token at an odd one, for a hybrid of their styles. Each
+    /// chain continues from the last tokens generated so far, as many as its own order, so the two
+    /// need not be of the same order. When the chain whose turn it is has never seen those tokens,
+    /// such as when the other chain just emitted a token only it knows, the other chain takes the
+    /// turn instead, and generation only stops early when neither of them can continue. Tokens
+    /// are returned in the most common surface form of the chain that picked them, if it tracks
+    /// them. The random number generator of `a` drives the whole generation.
+    pub fn generate_alternating(a: &Chain<T>, b: &Chain<T>) -> Vec<T> {
+        let mut rng = a.rng();
+        let mut history = Vec::new();
+        let mut ret = Vec::new();
+        loop {
+            let turn = if history.len() % 2 == 0 {
+                [a, b]
+            } else {
+                [b, a]
+            };
+            let step = turn.iter().find_map(|&chain| {
+                let mut state = vec![None; chain.order];
+                state.extend(history.iter().cloned().map(Some));
+                let state = state.split_off(state.len() - chain.order);
+                chain
+                    .map
+                    .get(&state)
+                    .filter(|nexts| nexts.values().any(|&count| count > 0.0))
+                    .map(|nexts| (chain, nexts.next(&mut rng)))
+            });
+            match step {
+                Some((chain, Some(next))) => {
+                    ret.push(chain.surface_form(next.clone()));
+                    history.push(next);
                 }
+                _ => break,
+            }
+        }
+        ret
+    }
 
-                states
-            })
-            .unique()
-            .map(|state| (state.clone(), graph.add_node(state)))
-            .collect::<HashMap<_, _>>();
+    /// Generates a collection of tokens from the chain just like `generate`, but biases the choice
+    /// of the first token by the average length of the sequences it began during training. Each
+    /// possible first token is picked in proportion to its count times that average length raised
+    /// to the power of `length_pref`, so a positive `length_pref` favors openings that led to
+    /// longer sequences, a negative one favors those that led to shorter ones, and zero is the same
+    /// as `generate`. The rest of the generation is left as it is. Tokens without any recorded
+    /// lengths, such as those fed through `feed_transitions`, are weighted as if their average
+    /// length were one.
+    pub fn generate_biased_start<R: Rng>(&self, length_pref: f64, rng: &mut R) -> Vec<T> {
+        let start = vec![None; self.order];
+        let nexts = match self.map.get(&start) {
+            Some(nexts) => nexts,
+            None => return Vec::new(),
+        };
+        let average = |token: &T| {
+            self.openings
+                .as_ref()
+                .and_then(|openings| openings.get(token))
+                .filter(|&&(weight, _)| weight > 0.0)
+                .map_or(1.0, |&(weight, length)| length / weight)
+        };
+        let first = weighted_choice(
+            nexts.iter().filter_map(|(next, &count)| {
+                let token = next.as_ref()?;
+                Some((next, count * average(token).powf(length_pref)))
+            }),
+            rng,
+        );
+        match first {
+            Some(Some(first)) => self.walk(
+                next_state(&start, &Some(first.clone())),
+                vec![first],
+                rng,
+                |_, nexts, _, rng| Some(nexts.next(rng)),
+            ),
+            _ => Vec::new(),
+        }
+    }
 
-        // Create all edges, and add them to the graph.
-        self.map
+    /// Generates `count` collections of tokens from the chain and sends each over the given
+    /// channel as soon as it is ready, for use as the producer in a concurrent pipeline. Since
+    /// the channel is bounded, this blocks whenever it is full, so a slow consumer throttles
+    /// generation instead of letting the output pile up. This stops early and returns an error
+    /// holding the unsent collection if the receiving end hangs up.
+    pub fn generate_into_channel(
+        &self,
+        tx: SyncSender<Vec<T>>,
+        count: usize,
+    ) -> ::std::result::Result<(), SendError<Vec<T>>>
+    where
+        T: Send,
+    {
+        for _ in 0..count {
+            tx.send(self.generate())?;
+        }
+        Ok(())
+    }
+
+    /// Generates a collection of tokens from the chain backward, starting from the end of a
+    /// sentence and working toward its beginning, and returns them in the usual forward order.
+    /// Each step picks a state leading into the current one in proportion to how often it did,
+    /// so for a chain fed only whole sequences, the output follows the same distribution as
+    /// `generate`. Since the last tokens are picked first, though, this is the building block for
+    /// generating toward a given ending, such as a rhyme. This builds an index of the transitions
+    /// in reverse on every call, which takes time and temporary memory in proportion to the
+    /// number of transitions in the chain.
+    pub fn generate_backward(&self) -> Vec<T> {
+        let mut rng = self.rng();
+        let predecessors = self.predecessors();
+        let endings = self
+            .map
             .iter()
-            .flat_map(|(state, nexts)| {
-                let sum = nexts.iter().map(|(_, p)| p).sum::<usize>() as f64;
+            .filter_map(|(state, nexts)| Some((state, *nexts.get(&None)?)))
+            .filter(|&(state, _)| state.iter().any(Option::is_some));
+        let mut state = match weighted_choice(endings, &mut rng) {
+            Some(state) => state,
+            None => return Vec::new(),
+        };
+        let mut ret = Vec::new();
+        while let Some(Some(token)) = state.last() {
+            ret.push(token.clone());
+            state = match predecessors.get(&state).and_then(|prevs| {
+                weighted_choice(prevs.iter().map(|(prev, count)| (prev, *count)), &mut rng)
+            }) {
+                Some(prev) => prev,
+                None => break,
+            };
+        }
+        ret.reverse();
+        self.surface(ret)
+    }
 
-                nexts
+    /// Generates a collection of tokens from the chain just like `generate`, but leaves out the
+    /// first `burn` tokens, for when the tokens right after the beginning of a sentence are
+    /// boilerplate. The walk still goes through the left out tokens, so the rest follow on from
+    /// them just as they would have otherwise. A `burn` at least as long as the generated sequence
+    /// gives an empty collection.
+    pub fn generate_with_burnin(&self, burn: usize) -> Vec<T> {
+        let mut ret = self.generate();
+        ret.drain(..burn.min(ret.len()));
+        ret
+    }
+
+    /// Generates a collection of tokens from the chain just like `generate`, but cuts it off at the
+    /// first occurrence of `boundary`, keeping the boundary itself only if `inclusive` is set. This
+    /// picks out the first segment of the output when the chain was fed separators within its
+    /// sequences, such as after merging chains. If the boundary never occurs, the whole collection
+    /// is returned.
+    pub fn generate_clamped_at(&self, boundary: &T, inclusive: bool) -> Vec<T> {
+        let mut ret = self.generate();
+        if let Some(i) = ret.iter().position(|token| token == boundary) {
+            ret.truncate(if inclusive { i + 1 } else { i });
+        }
+        ret
+    }
+
+    /// Generates a collection of tokens from the chain just like `generate`, pairing each token
+    /// with the Shannon entropy, in bits, of the distribution of successors it was picked from.
+    /// An entropy of zero means the chain had no choice at all at that step, while higher values
+    /// mean it was choosing between more, and more evenly weighted, alternatives. This is useful
+    /// for highlighting the parts of the output where the chain was merely guessing.
+    pub fn generate_with_step_entropy(&self) -> Vec<(T, f64)> {
+        let mut rng = self.rng();
+        let mut entropies = Vec::new();
+        let tokens = self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |_, nexts, _, rng| {
+                entropies.push(nexts.entropy());
+                Some(nexts.next(rng))
+            },
+        );
+        tokens.into_iter().zip(entropies).collect()
+    }
+
+    /// Generates a collection of tokens from the chain using only the tokens in `allowed`. At each
+    /// step, successors outside of the set are ignored and one of the others is picked in
+    /// proportion to its count, as if the ignored ones had never been seen. The end of a sentence
+    /// is always allowed. When a state has no allowed successors at all, generation stops right
+    /// there, so the output may end where no sentence in the training data did.
+    pub fn generate_within(&self, allowed: &HashSet<T>) -> Vec<T> {
+        let mut rng = self.rng();
+        self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |_, nexts, _, rng| {
+                let within = nexts
                     .iter()
-                    .map(|(next, p)| (state.clone(), next.clone(), *p as f64 / sum))
-                    .collect::<Vec<_>>()
-            })
-            .for_each(|(state, next, p)| {
-                let mut next_state = state.clone();
-                next_state.remove(0);
-                next_state.push(next);
+                    .filter(|&(next, _)| next.as_ref().is_none_or(|token| allowed.contains(token)))
+                    .map(|(next, &count)| (next, count));
+                weighted_choice(within, rng)
+            },
+        )
+    }
 
-                graph.add_edge(state_map[&state], state_map[&next_state], p);
+    /// Generates a collection of tokens from the chain just like `generate`, pairing each token
+    /// with its rank among the successors it was picked from: `0` if it was the most likely
+    /// choice, `1` if exactly one other successor was more likely, and so on. Equally likely
+    /// successors share a rank. This shows how adventurous each choice was.
+    pub fn generate_with_ranks(&self) -> Vec<(T, usize)> {
+        let mut rng = self.rng();
+        let mut ranks = Vec::new();
+        let tokens = self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |_, nexts, _, rng| {
+                let next = nexts.next(rng);
+                let count = nexts.get(&next).cloned().unwrap_or(0.0);
+                ranks.push(nexts.values().filter(|&&other| other > count).count());
+                Some(next)
+            },
+        );
+        tokens.into_iter().zip(ranks).collect()
+    }
+
+    /// Generates a collection of tokens from the chain, weighting every candidate transition with
+    /// the given closure instead of its count. At each step, `score(from, to, count)` is called for
+    /// each successor, where `from` is the last token emitted, `to` the successor, and `count` the
+    /// number of times the transition was seen, and the successor is then picked in proportion to
+    /// the returned weight. `None` stands for the beginning of a sentence as `from` and for its end
+    /// as `to`. Successors with a weight that is not positive are never picked, so returning zero
+    /// bans a transition, and generation stops as soon as every successor is banned. Returning
+    /// `count` unchanged is the same as `generate`.
+    pub fn generate_scored<F>(&self, score: F) -> Vec<T>
+    where
+        F: Fn(Option<&T>, Option<&T>, f64) -> f64,
+    {
+        let mut rng = self.rng();
+        self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |curs, nexts, _, rng| {
+                let from = curs.last().and_then(Option::as_ref);
+                let scored = nexts
+                    .iter()
+                    .map(|(next, &count)| (next, score(from, next.as_ref(), count)));
+                weighted_choice(scored, rng)
+            },
+        )
+    }
+
+    /// Generates a collection of tokens from the chain, favoring rare and distinctive tokens over
+    /// common filler. The frequency `f(t)` of a token is the number of times it followed anything
+    /// in the whole chain, out of `N` such times for all tokens, and each successor `t` is weighted
+    /// by `count · (1 + strength · ln(N / f(t)))`, in the spirit of inverse document frequency.
+    /// The end of a sentence is never boosted. A `strength` of zero is the same as `generate`, and
+    /// it should never be negative.
+    pub fn generate_favor_rare(&self, strength: f64) -> Vec<T> {
+        let mut frequencies = HashMap::new();
+        for nexts in self.map.values() {
+            for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                if let Some(ref token) = *next {
+                    *frequencies.entry(token).or_insert(0.0) += count;
+                }
+            }
+        }
+        let total = frequencies.values().sum::<f64>();
+        self.generate_scored(|_, to, count| match to {
+            Some(token) => frequencies.get(token).map_or(count, |&frequency| {
+                count * (1.0 + strength * (total / frequency).ln())
+            }),
+            None => count,
+        })
+    }
+
+    /// Generates a collection of tokens from the chain, starting from the beginning of a sentence
+    /// just like `generate`. The first token is therefore always one that actually began a
+    /// sentence during training. Unlike `generate`, this never panics: it returns `None` if the
+    /// chain is empty, and simply ends the output early if the walk reaches a state with no
+    /// successors.
+    pub fn generate_realistic(&self) -> Option<Vec<T>> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut rng = self.rng();
+        Some(self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |_, nexts, _, rng| {
+                weighted_choice(nexts.iter().map(|(next, &count)| (next, count)), rng)
+            },
+        ))
+    }
+
+    /// Generates a collection of tokens from the chain using nucleus (top-p) sampling. At each
+    /// step, only the most likely successors are considered: the smallest set of them whose
+    /// probabilities add up to at least `p`. One of those is then picked, in proportion to its
+    /// count. The single most likely successor is always considered, so a very small `p` makes
+    /// this behave greedily, while a `p` of `1.0` is the same as `generate`. Leaving out unlikely
+    /// successors can leave the chain stuck in a cycle without any way to reach the end of a
+    /// sentence, in which case the output is cut off as described for `generate_with_sampler`.
+    pub fn generate_top_p(&self, p: f64) -> Vec<T> {
+        self.generate_with_sampler(&TopP(p))
+    }
+
+    /// Generates a collection of tokens from the chain using top-k sampling. At each step, only
+    /// the `k` most frequently seen successors are considered, and one of those is picked in
+    /// proportion to its count. States with fewer than `k` successors consider all of them. A `k`
+    /// of one always picks the most likely successor, and a `k` of zero generates nothing. As with
+    /// `generate_top_p`, a small `k` can leave the chain stuck in a cycle, and the output is then
+    /// cut off.
+    pub fn generate_top_k(&self, k: usize) -> Vec<T> {
+        self.generate_with_sampler(&TopK(k))
+    }
+
+    /// Generates a collection of tokens from the chain, leaving the choice of every token to the
+    /// given sampler. The built-in strategies are `Proportional`, which is the same as `generate`,
+    /// `Greedy`, `TopK`, and `TopP`, and any other can be plugged in by implementing `Sampler`.
+    /// Generation stops once the sampler picks the end of a sentence or picks nothing at all. A
+    /// sampler that leaves out some successors can get stuck in a cycle without any way to reach
+    /// the end of a sentence, so to guarantee that generation ends, the output is cut off once it
+    /// has as many tokens as the combined counts of every transition in the chain, or as it has
+    /// states if that is more. That is, it may not grow longer than all of the training data put
+    /// together, which with `Proportional` is vanishingly unlikely in a chain of any size.
+    pub fn generate_with_sampler(&self, sampler: &dyn Sampler<T>) -> Vec<T> {
+        let total = self.map.values().flat_map(HashMap::values).sum::<f64>();
+        let cap = (total.ceil() as usize).max(self.map.len());
+        let mut rng = self.rng();
+        self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |_, nexts, emitted, rng| {
+                if emitted.len() >= cap {
+                    return None;
+                }
+                sampler.pick(nexts, rng)
+            },
+        )
+    }
+
+    /// Generates a collection of tokens from the chain that never visits the same state twice, so
+    /// no phrase of the order of the chain is ever repeated. At each step, successors that would
+    /// return to an already visited state are ruled out and one of the rest is picked in
+    /// proportion to its count. If every successor has been ruled out, the walk is stuck and the
+    /// output simply ends there, even though the end of a sentence was not reached.
+    pub fn generate_self_avoiding(&self) -> Vec<T> {
+        let mut rng = self.rng();
+        let mut visited = HashSet::new();
+        visited.insert(vec![None; self.order]);
+        self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |curs, nexts, _, rng| {
+                let next = weighted_choice(
+                    nexts
+                        .iter()
+                        .filter(|&(next, _)| {
+                            next.is_none() || !visited.contains(&next_state(curs, next))
+                        })
+                        .map(|(next, &count)| (next, count)),
+                    rng,
+                )?;
+                visited.insert(next_state(curs, &next));
+                Some(next)
+            },
+        )
+    }
+
+    /// Generates up to `n` collections of tokens that all differ noticeably from each other. Each
+    /// candidate is kept only if its token-level edit distance to every output kept so far is
+    /// greater than `min_edit_distance`. So that this terminates even on chains that can only
+    /// produce a few distinct outputs, it gives up after ten attempts per requested output, and
+    /// may therefore return fewer than `n` outputs. An empty chain produces none at all.
+    pub fn generate_diverse_set(&self, n: usize, min_edit_distance: usize) -> Vec<Vec<T>> {
+        let mut kept: Vec<Vec<T>> = Vec::new();
+        for _ in 0..n.saturating_mul(10) {
+            if kept.len() >= n {
+                break;
+            }
+            let candidate = match self.generate_realistic() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            if kept
+                .iter()
+                .all(|other| edit_distance(&candidate, other) > min_edit_distance)
+            {
+                kept.push(candidate);
+            }
+        }
+        kept
+    }
+
+    /// Generates a collection of tokens from the chain that includes every one of the `required`
+    /// tokens somewhere, for "use these words in a sentence" prompts. This is rejection sampling:
+    /// it generates up to `max_attempts` collections and returns the first that satisfies the
+    /// constraint, or `None` if none of them did. It gives up straight away if a required token
+    /// was never seen, as no attempt could ever succeed. The more required tokens and the rarer
+    /// they are, the more attempts are needed.
+    pub fn generate_including(&self, required: &HashSet<T>, max_attempts: usize) -> Option<Vec<T>> {
+        if !required.iter().all(|token| self.contains(token)) {
+            return None;
+        }
+        (0..max_attempts)
+            .map(|_| self.generate())
+            .find(|candidate| required.iter().all(|token| candidate.contains(token)))
+    }
+
+    /// Generates a collection of tokens from the chain, starting with the given token. This
+    /// operation is O(mn) where m is the length of the generated collection, and n is the number
+    /// of possible states from a given state. This returns an empty vector if the token is not
+    /// found.
+    pub fn generate_from_token(&self, token: T) -> Vec<T> {
+        let mut curs = vec![None; self.order - 1];
+        curs.push(Some(token.clone()));
+        if !self.map.contains_key(&curs) {
+            return Vec::new();
+        }
+        let mut rng = self.rng();
+        self.walk(curs, vec![token], &mut rng, |_, nexts, _, rng| {
+            Some(nexts.next(rng))
+        })
+    }
+
+    /// Suggests the most likely continuation of `from`, of up to `k` tokens, for type-ahead style
+    /// completion. This is found greedily: each step takes the successor seen most often, and the
+    /// continuation ends early at the end of a sentence or where the chain has no successors. In
+    /// chains of higher order, it continues from the context of `from` seen most often. The
+    /// continuation does not include `from` itself, and is empty if the token is not found.
+    pub fn best_continuation(&self, from: &T, k: usize) -> Vec<T> {
+        let from = Some(from.clone());
+        let state = self
+            .map
+            .iter()
+            .filter(|&(state, _)| state.last() == Some(&from))
+            .max_by(|a, b| {
+                let total = |nexts: &HashMap<Token<T>, f64>| nexts.values().sum::<f64>();
+                total(a.1).total_cmp(&total(b.1))
             });
+        let state = match state {
+            Some((state, _)) => state.clone(),
+            None => return Vec::new(),
+        };
+        self.walk(state, Vec::new(), &mut self.rng(), |_, nexts, ret, _| {
+            if ret.len() >= k {
+                return None;
+            }
+            sorted_by_count(nexts)
+                .first()
+                .map(|&(next, _)| next.clone())
+        })
+    }
 
-        graph
+    /// Generates a collection of tokens that begins with `start_tok` and ends with `end_tok`, at
+    /// most `max_len` tokens long in total, or returns `None` if the chain has no such path. As in
+    /// `generate_from_token`, chains of higher order only start from `start_tok` where it began a
+    /// sentence. Rather than generating sentences and rejecting those that do not fit, this is a
+    /// directed random walk: it first computes how many steps every state needs to reach
+    /// `end_tok`, and then, starting from `start_tok`, only ever picks successors from which
+    /// `end_tok` can still be reached within the remaining length, in proportion to their counts.
+    /// The walk stops as soon as it emits `end_tok`, whether or not the chain ever saw a sentence
+    /// end there. Every path that fits is therefore possible, and no attempts are wasted.
+    pub fn generate_between(&self, start_tok: T, end_tok: T, max_len: usize) -> Option<Vec<T>> {
+        let mut curs = vec![None; self.order - 1];
+        curs.push(Some(start_tok.clone()));
+        if max_len == 0 || !self.map.contains_key(&curs) {
+            return None;
+        }
+        let mut ret = vec![start_tok];
+        let target = Some(end_tok);
+        if ret.last() == target.as_ref() {
+            return Some(self.surface(ret));
+        }
+        let distances = self.distances_to(&target);
+        if distances
+            .get(&curs)
+            .is_none_or(|&d| ret.len() + d > max_len)
+        {
+            return None;
+        }
+        let mut rng = self.rng();
+        loop {
+            let nexts = self.map.get(&curs)?;
+            let len = ret.len() + 1;
+            let feasible = nexts.iter().filter(|&(next, _)| {
+                *next == target
+                    || next.is_some()
+                        && distances
+                            .get(&next_state(&curs, next))
+                            .is_some_and(|&d| len + d <= max_len)
+            });
+            let next = weighted_choice(feasible.map(|(next, &count)| (next, count)), &mut rng)?;
+            curs = next_state(&curs, &next);
+            let done = next == target;
+            ret.push(next?);
+            if done {
+                return Some(self.surface(ret));
+            }
+        }
     }
-}
 
-#[cfg(feature = "yaml")]
-impl<T> Chain<T>
-where
-    T: Chainable + Serialize,
-{
-    /// Saves the current chain to the specified path.
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let mut file = File::create(&path)?;
-        let data = yaml::to_string(self).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
-        file.write_all(data.as_bytes())?;
-        Ok(())
+    /// Generates a collection of tokens from the chain, starting with the given tokens. The last
+    /// `order` of them are the state generation continues from, and if there are fewer than that,
+    /// they are taken to begin a sentence. This returns an empty vector if no tokens are given or
+    /// if the chain never saw them in that position.
+    pub fn generate_from_tokens(&self, tokens: Vec<T>) -> Vec<T> {
+        let mut curs = vec![None; self.order];
+        curs.extend(tokens.iter().map(|token| Some(token.clone())));
+        let curs = curs.split_off(curs.len() - self.order);
+        if tokens.is_empty() || !self.map.contains_key(&curs) {
+            return Vec::new();
+        }
+        let mut rng = self.rng();
+        self.walk(curs, tokens, &mut rng, |_, nexts, _, rng| {
+            Some(nexts.next(rng))
+        })
+    }
+
+    /// Merges 2 chains (self and other) into self, consuming the other one. Both chains must be of
+    /// the same order. This method is useful when you want to speed up chain building - chains
+    /// built independently (e.g. in parallel with rayon) can be merged into a final one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chains are of different orders.
+    pub fn merge(&mut self, other: Chain<T>) -> &Chain<T> {
+        assert!(self.order == other.order);
+
+        for (tokens, next) in other.map {
+            self.map.entry(tokens.clone()).or_default();
+
+            for (token, count) in next {
+                self.reinforce(&tokens, &token, count);
+            }
+        }
+        self.evict();
+
+        if let Some(other_orders) = other.lower_orders {
+            let orders = self
+                .lower_orders
+                .get_or_insert_with(|| vec![HashMap::new(); other_orders.len()]);
+            for (map, other_map) in orders.iter_mut().zip(other_orders) {
+                for (state, nexts) in other_map {
+                    let states = map.entry(state).or_default();
+                    for (next, count) in nexts {
+                        states.add(next, count);
+                    }
+                }
+            }
+        }
+
+        if let Some(other_lengths) = other.lengths {
+            let lengths = self.lengths.get_or_insert_with(BTreeMap::new);
+            for (length, count) in other_lengths {
+                *lengths.entry(length).or_insert(0.0) += count;
+            }
+        }
+
+        if let Some(other_openings) = other.openings {
+            let openings = self.openings.get_or_insert_with(HashMap::new);
+            for (token, (weight, length)) in other_openings {
+                let opening = openings.entry(token).or_insert((0.0, 0.0));
+                opening.0 += weight;
+                opening.1 += length;
+            }
+        }
+
+        if let Some(other_tagged) = other.tagged {
+            let tagged = self.tagged.get_or_insert_with(HashMap::new);
+            for (id, sequences) in other_tagged {
+                tagged.entry(id).or_default().extend(sequences);
+            }
+        }
+
+        if let Some(other_forms) = other.surface_forms {
+            let forms = self.surface_forms.get_or_insert_with(HashMap::new);
+            for (token, seen) in other_forms {
+                let counts = forms.entry(token).or_default();
+                for (form, count) in seen {
+                    *counts.entry(form).or_insert(0) += count;
+                }
+            }
+        }
+
+        self
+    }
+
+    /// Builds a single chain out of many, with the counts of each scaled by its weight, as an
+    /// ensemble of the inputs. This does in one pass what repeatedly calling `merge` would, without
+    /// consuming or copying the inputs, and sizes the map of the result only once. The lengths of
+    /// the sequences fed into each input are scaled and combined the same way, and the surface
+    /// forms of case-insensitive inputs are combined as they are, but tags, aliases, and the
+    /// statistics of lower orders are left behind, and the result has the default settings. All
+    /// of the inputs must be of the same order. Merging no chains at all gives an empty chain of
+    /// order one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chains are of different orders.
+    pub fn merge_all(chains: &[(&Chain<T>, f64)]) -> Chain<T> {
+        let order = chains.first().map_or(1, |&(chain, _)| chain.order);
+        assert!(chains.iter().all(|&(chain, _)| chain.order == order));
+        let mut merged = Chain::of_order(order);
+        merged.map = HashMap::with_capacity(chains.iter().map(|&(chain, _)| chain.map.len()).sum());
+        for &(chain, weight) in chains {
+            for (state, nexts) in &chain.map {
+                let states = merged.map.entry(state.clone()).or_default();
+                for (next, &count) in nexts {
+                    states.add(next.clone(), count * weight);
+                }
+            }
+            if let Some(ref lengths) = chain.lengths {
+                let merged_lengths = merged.lengths.get_or_insert_with(BTreeMap::new);
+                for (&length, &count) in lengths {
+                    *merged_lengths.entry(length).or_insert(0.0) += count * weight;
+                }
+            }
+            if let Some(ref openings) = chain.openings {
+                let merged_openings = merged.openings.get_or_insert_with(HashMap::new);
+                for (token, &(count, length)) in openings {
+                    let opening = merged_openings.entry(token.clone()).or_insert((0.0, 0.0));
+                    opening.0 += count * weight;
+                    opening.1 += length * weight;
+                }
+            }
+            if let Some(ref forms) = chain.surface_forms {
+                let merged_forms = merged.surface_forms.get_or_insert_with(HashMap::new);
+                for (token, seen) in forms {
+                    let counts = merged_forms.entry(token.clone()).or_default();
+                    for (form, &count) in seen {
+                        *counts.entry(form.clone()).or_insert(0) += count;
+                    }
+                }
+            }
+        }
+        merged
+    }
+
+    /// Samples a length from the distribution of the lengths of the sequences fed into the chain,
+    /// each length being picked in proportion to how often a sequence of that length was fed.
+    /// Passing the result to `generate_targeting_length` makes the lengths of the output resemble
+    /// those of the training data. Lengths are recorded by the methods that feed whole sequences,
+    /// taking their weight into account, but not by `feed_transitions`, which has no notion of a
+    /// sequence. This returns zero if no lengths were recorded.
+    pub fn sample_target_length<R: Rng>(&self, rng: &mut R) -> usize {
+        self.lengths
+            .as_ref()
+            .and_then(|lengths| {
+                weighted_choice(lengths.iter().map(|(length, &count)| (length, count)), rng)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Generates a collection of tokens from the chain, softly steering it towards a length of
+    /// `target` tokens. Once, before generation starts, every state is given an estimate of its
+    /// distance to the end of a sentence: the fewest transitions needed to get there. At each
+    /// step, each successor is then weighted by its count multiplied by `e^(-k * d)`, where `k` is
+    /// the number of tokens emitted so far divided by `target` and `d` is how much further from
+    /// the end the successor is than the closest of its alternatives. Early on this leaves the
+    /// chain nearly untouched, but as the output nears and then passes `target`, successors that
+    /// end the sentence sooner are increasingly favored. The output is never truncated, so this is
+    /// only approximate: it cannot shorten a path that has no way to end sooner, and it does not
+    /// lengthen output that reaches the end of a sentence early. States from which the end cannot
+    /// be reached at all are treated as being further from it than any other state.
+    pub fn generate_targeting_length(&self, target: usize) -> Vec<T> {
+        let distances = self.distances_to_end();
+        let unreachable = distances.len() + 1;
+        let distance = |curs: &[Token<T>], next: &Token<T>| match *next {
+            Some(_) => *distances
+                .get(&next_state(curs, next))
+                .unwrap_or(&unreachable),
+            None => 0,
+        };
+        let mut rng = self.rng();
+        self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            &mut rng,
+            |curs, nexts, emitted, rng| {
+                let pressure = emitted.len() as f64 / target.max(1) as f64;
+                let closest = nexts.keys().map(|next| distance(curs, next)).min()?;
+                let weights = nexts.iter().map(|(next, &count)| {
+                    let further = (distance(curs, next) - closest) as f64;
+                    (next, count * (-pressure * further).exp())
+                });
+                weighted_choice(weights, rng)
+            },
+        )
+    }
+
+    /// Computes the Kullback-Leibler divergence of the transitions of this chain from those of
+    /// `other`, in nats. For every state known to both chains, this compares the probabilities of
+    /// their successors, and the result is the average of those per-state divergences, weighted by
+    /// how often this chain visited each state. States known to only one of the chains are
+    /// ignored, and if there are no states in common, the divergence is zero. To handle successors
+    /// seen by only one of the chains, both distributions are smoothed by adding half a count to
+    /// every successor seen by either of them. Note that the divergence is asymmetric: it measures
+    /// how poorly `other` predicts this chain, and swapping the chains generally gives a different
+    /// result. Both chains must be of the same order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chains are of different orders.
+    pub fn kl_divergence(&self, other: &Chain<T>) -> f64 {
+        assert!(self.order == other.order);
+        let mut divergence = 0.0;
+        let mut visits = 0.0;
+        for (state, nexts) in &self.map {
+            let other_nexts = match other.map.get(state) {
+                Some(other_nexts) if !nexts.is_empty() && !other_nexts.is_empty() => other_nexts,
+                _ => continue,
+            };
+            let support = nexts
+                .keys()
+                .chain(other_nexts.keys())
+                .collect::<HashSet<_>>();
+            let smoothed = |nexts: &HashMap<Token<T>, f64>, next: &Token<T>| {
+                let total = nexts.values().sum::<f64>() + 0.5 * support.len() as f64;
+                (nexts.get(next).cloned().unwrap_or(0.0) + 0.5) / total
+            };
+            let state_divergence = support
+                .iter()
+                .map(|next| {
+                    let p = smoothed(nexts, next);
+                    p * (p / smoothed(other_nexts, next)).ln()
+                })
+                .sum::<f64>();
+            let state_visits = nexts.values().sum::<f64>();
+            divergence += state_divergence * state_visits;
+            visits += state_visits;
+        }
+        if visits == 0.0 {
+            return 0.0;
+        }
+        divergence / visits
+    }
+
+    /// Picks one of the tokens known to the chain uniformly at random, regardless of how often it
+    /// was seen. This returns `None` if the chain is empty.
+    pub fn random_state<R: Rng>(&self, rng: &mut R) -> Option<T> {
+        let tokens = self.tokens();
+        if tokens.is_empty() {
+            return None;
+        }
+        Some(tokens[rng.gen_range(0..tokens.len())].clone())
+    }
+
+    /// Picks a successor of `from` in proportion to how often it followed it, leaving out the end
+    /// of a sentence, so that the odds of the other successors are scaled up to make up for it.
+    /// This is a building block for generations that should keep going for a while rather than
+    /// stop. It returns `None` if `from` was never followed by anything but the end of a sentence,
+    /// in which case the dead end cannot be avoided, or if it was never seen at all. In chains of
+    /// higher order, this picks among the successors of every state ending with `from`.
+    pub fn step_no_end<R: Rng>(&self, from: &T, rng: &mut R) -> Option<T> {
+        let successors = self.successors(from);
+        weighted_choice(
+            successors
+                .iter()
+                .filter(|&(next, _)| next.is_some())
+                .map(|(next, &count)| (next, count)),
+            rng,
+        )
+        .and_then(|next| next)
+    }
+
+    /// Measures how concentrated the successors of `from` are with the Gini coefficient of their
+    /// counts, including that of the end of a sentence, scaled by `n / (n - 1)` for `n` successors
+    /// so that its range does not depend on how many there are. It is zero when every successor
+    /// was seen equally often, and approaches one as a single successor comes to dominate the
+    /// rest, so it sets apart states with predictable continuations from those with varied ones,
+    /// much like the entropy of the successors but on a fixed scale. A token with fewer than two
+    /// successors, or that was never seen, gives zero. In chains of higher order, this measures
+    /// the combined successors of every state ending with `from`.
+    pub fn successor_concentration(&self, from: &T) -> f64 {
+        let counts = self
+            .successors(from)
+            .values()
+            .cloned()
+            .filter(|&count| count > 0.0)
+            .collect::<Vec<_>>();
+        if counts.len() < 2 {
+            return 0.0;
+        }
+        let total = counts.iter().sum::<f64>();
+        let differences = counts
+            .iter()
+            .map(|x| counts.iter().map(|y| (x - y).abs()).sum::<f64>())
+            .sum::<f64>();
+        differences / (2.0 * (counts.len() - 1) as f64 * total)
+    }
+
+    /// Measures how alike the successors of two tokens are with the cosine similarity of their
+    /// counts, treating the end of a sentence as a successor like any other. Since counts are
+    /// never negative, this ranges from zero, for tokens that share no successors, to one, for
+    /// tokens whose successors are in the same proportions, however often each was seen. Tokens
+    /// that score highly behave alike, which makes them candidates for clustering or for
+    /// `add_alias`. A token that was never seen, or has no successors, gives zero. In chains of
+    /// higher order, this compares the combined successors of every state ending with each token.
+    pub fn successor_similarity(&self, a: &T, b: &T) -> f64 {
+        let a = self.successors(a);
+        let b = self.successors(b);
+        let norm = |nexts: &HashMap<Token<T>, f64>| {
+            nexts
+                .values()
+                .map(|count| count * count)
+                .sum::<f64>()
+                .sqrt()
+        };
+        let norms = norm(&a) * norm(&b);
+        if norms == 0.0 {
+            return 0.0;
+        }
+        let dot = a
+            .iter()
+            .filter_map(|(next, count)| b.get(next).map(|other| count * other))
+            .sum::<f64>();
+        dot / norms
+    }
+
+    /// Finds a longest path from the beginning of a sentence to its end that never visits the same
+    /// state twice, which bounds how long a sentence the chain can plausibly generate. Finding it
+    /// is NP-hard in general, so this is an exhaustive depth-first search with a budget: it steps
+    /// into at most a million states in total, and if that is not enough to try every path, it
+    /// returns the longest one found so far. For small chains the result is therefore exact, and
+    /// for large ones it is a lower bound. This returns an empty collection if the chain is empty.
+    pub fn longest_path(&self) -> Vec<T> {
+        let start = vec![None; self.order];
+        let mut best = Vec::new();
+        let mut path = Vec::new();
+        let mut on_path = HashSet::new();
+        on_path.insert(start.clone());
+        let mut stack = vec![(start.clone(), self.possible_successors(&start))];
+        let mut budget = 1_000_000usize;
+        while let Some((state, nexts)) = stack.last_mut() {
+            match nexts.pop() {
+                Some(None) => {
+                    if path.len() > best.len() {
+                        best = path.clone();
+                    }
+                }
+                Some(Some(token)) => {
+                    let next = next_state(state, &Some(token.clone()));
+                    if on_path.contains(&next) || !self.map.contains_key(&next) {
+                        continue;
+                    }
+                    if budget == 0 {
+                        break;
+                    }
+                    budget -= 1;
+                    on_path.insert(next.clone());
+                    path.push(token);
+                    let nexts = self.possible_successors(&next);
+                    stack.push((next, nexts));
+                }
+                None => {
+                    if let Some((state, _)) = stack.pop() {
+                        on_path.remove(&state);
+                    }
+                    path.pop();
+                }
+            }
+        }
+        self.surface(best)
+    }
+
+    /// Lists every distinct collection of tokens the chain can generate that is at most `max_len`
+    /// tokens long, in no particular order. Each is a path from the beginning of a sentence to its
+    /// end, and paths may go around cycles as long as they stay within the length limit. The
+    /// number of paths can grow exponentially with `max_len`, as every branch multiplies it, so
+    /// this is only viable for small chains or short lengths.
+    pub fn enumerate_paths(&self, max_len: usize) -> Vec<Vec<T>> {
+        let start = vec![None; self.order];
+        let mut paths = Vec::new();
+        let mut path = Vec::new();
+        let mut stack = vec![(start.clone(), self.possible_successors(&start))];
+        while let Some((state, nexts)) = stack.last_mut() {
+            match nexts.pop() {
+                Some(None) => paths.push(self.surface(path.clone())),
+                Some(Some(token)) => {
+                    if path.len() >= max_len {
+                        continue;
+                    }
+                    let next = next_state(state, &Some(token.clone()));
+                    path.push(token);
+                    let nexts = self.possible_successors(&next);
+                    stack.push((next, nexts));
+                }
+                None => {
+                    stack.pop();
+                    path.pop();
+                }
+            }
+        }
+        paths
+    }
+
+    /// Computes the matrix of transition probabilities between the states of the chain, for
+    /// analysis with external numeric libraries. The states are returned along with the matrix,
+    /// each as the `order` tokens it is made of, with `None` for the beginning of a sentence; the
+    /// beginning of a sentence itself comes first, followed by the others in an order that stays
+    /// the same for as long as the chain is left unchanged. The entry at row `i` and column `j` is
+    /// the probability of moving from state `i` to state `j`. As in `random_walk`, the end of a
+    /// sentence leads straight back to the beginning of the next one, which makes every row sum to
+    /// one, except for those of states without any successors, which sum to zero.
+    pub fn transition_matrix(&self) -> (Vec<Vec<Option<T>>>, Vec<Vec<f64>>) {
+        let start = vec![None; self.order];
+        let mut states = vec![start.clone()];
+        states.extend(self.map.keys().filter(|&state| *state != start).cloned());
+        let mut index = states
+            .iter()
+            .enumerate()
+            .map(|(i, state)| (state.clone(), i))
+            .collect::<HashMap<_, _>>();
+        let mut edges = Vec::new();
+        for (i, state) in states.clone().iter().enumerate() {
+            let nexts = match self.map.get(state) {
+                Some(nexts) => nexts,
+                None => continue,
+            };
+            let total = nexts.values().filter(|&&count| count > 0.0).sum::<f64>();
+            for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                let next = match *next {
+                    Some(_) => next_state(state, next),
+                    None => start.clone(),
+                };
+                let j = *index.entry(next.clone()).or_insert_with(|| {
+                    states.push(next);
+                    states.len() - 1
+                });
+                edges.push((i, j, count / total));
+            }
+        }
+        let mut matrix = vec![vec![0.0; states.len()]; states.len()];
+        for (i, j, p) in edges {
+            matrix[i][j] += p;
+        }
+        (states, matrix)
+    }
+
+    /// Computes the probability of emitting `to` exactly `k` tokens after `from`. As in
+    /// `transition_matrix`, the end of a sentence leads straight back to the beginning of the next
+    /// one, and counts as a step of its own. This is found by pushing the distribution over states
+    /// forward `k` times, one step at a time, keeping track of only the states that can actually
+    /// be reached, so it costs `O(ke)` in the number of transitions `e` at worst, and much less
+    /// when few states are reachable from `from` within `k` steps. In chains of higher order, the
+    /// walk starts from every state ending with `from`, in proportion to how often each was seen.
+    /// This gives zero if `from` was never seen.
+    pub fn k_step_probability(&self, from: &T, to: &T, k: usize) -> f64 {
+        let from = Some(from.clone());
+        let to = Some(to.clone());
+        let mut dist = self
+            .map
+            .iter()
+            .filter(|&(state, _)| state.last() == Some(&from))
+            .map(|(state, nexts)| {
+                let count = nexts.values().filter(|&&count| count > 0.0).sum::<f64>();
+                (state.clone(), count)
+            })
+            .filter(|&(_, count)| count > 0.0)
+            .collect::<HashMap<_, _>>();
+        let total = dist.values().sum::<f64>();
+        if total == 0.0 {
+            return 0.0;
+        }
+        let start = vec![None; self.order];
+        for _ in 0..k {
+            let mut next_dist = HashMap::new();
+            for (state, mass) in dist {
+                let nexts = match self.map.get(&state) {
+                    Some(nexts) => nexts,
+                    None => continue,
+                };
+                let sum = nexts.values().filter(|&&count| count > 0.0).sum::<f64>();
+                for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                    let next = match *next {
+                        Some(_) => next_state(&state, next),
+                        None => start.clone(),
+                    };
+                    *next_dist.entry(next).or_insert(0.0) += mass * count / sum;
+                }
+            }
+            dist = next_dist;
+        }
+        dist.iter()
+            .filter(|&(state, _)| state.last() == Some(&to))
+            .map(|(_, mass)| mass)
+            .sum::<f64>()
+            / total
+    }
+
+    /// Estimates the mixing time of the chain: the number of steps after which the distribution
+    /// over its states, starting from the beginning of a sentence, is within `epsilon` of the
+    /// stationary distribution in total variation distance. As in `transition_matrix`, the end of
+    /// a sentence leads straight back to the beginning of the next one, and so do states without
+    /// any successors. The stationary distribution is itself found by power iteration on a lazy
+    /// version of the chain, which has the same stationary distribution but always converges.
+    ///
+    /// Each step is a product of the distribution with the transition matrix, which is `O(e)` in
+    /// the number of transitions `e`, and chains that mix slowly may take many steps, so this can
+    /// be slow on large chains. Chains that never mix, such as periodic ones, give `usize::MAX`
+    /// once a bound of steps proportional to their size is reached.
+    pub fn mixing_time(&self, epsilon: f64) -> usize {
+        let (states, matrix) = self.transition_matrix();
+        let rows = matrix
+            .iter()
+            .map(|row| {
+                let row = row
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &p)| p > 0.0)
+                    .map(|(j, &p)| (j, p))
+                    .collect::<Vec<_>>();
+                if row.is_empty() {
+                    vec![(0, 1.0)]
+                } else {
+                    row
+                }
+            })
+            .collect::<Vec<_>>();
+        let step = |dist: &[f64]| {
+            let mut next = vec![0.0; dist.len()];
+            for (i, row) in rows.iter().enumerate() {
+                for &(j, p) in row {
+                    next[j] += dist[i] * p;
+                }
+            }
+            next
+        };
+        let distance =
+            |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum::<f64>() / 2.0;
+        let bound = 1000 * states.len();
+        let mut stationary = vec![1.0 / states.len() as f64; states.len()];
+        for _ in 0..bound {
+            let next = step(&stationary)
+                .iter()
+                .zip(&stationary)
+                .map(|(p, q)| (p + q) / 2.0)
+                .collect::<Vec<_>>();
+            let converged = distance(&next, &stationary) < 1e-12;
+            stationary = next;
+            if converged {
+                break;
+            }
+        }
+        let mut dist = vec![0.0; states.len()];
+        dist[0] = 1.0;
+        for t in 0..bound {
+            if distance(&dist, &stationary) <= epsilon {
+                return t;
+            }
+            dist = step(&dist);
+        }
+        usize::MAX
+    }
+
+    /// Exports the chain as a `CompactChain`, a read-only model for deployment that keeps only the
+    /// probabilities of the transitions, as `f32`, in contiguous arrays for fast sampling and a
+    /// small footprint. Tokens are stored in their most common surface forms, if the chain tracks
+    /// them. A compact chain can generate just like this one, but always stops at dead ends
+    /// regardless of the dead end policy, and cannot be trained any further. Transitions into
+    /// states from which generation could never stop, such as those of `absorbing_states`, are
+    /// left out, so that generating from the compact chain always finishes. If this chain is
+    /// seeded, the compact chain gets a copy of its random number generator in its current state,
+    /// so its generations are reproducible too, though they differ from those of this chain.
+    pub fn to_compact(&self) -> CompactChain<T> {
+        let start = vec![None; self.order];
+        let mut states = vec![start.clone()];
+        states.extend(
+            self.map
+                .iter()
+                .filter(|&(state, nexts)| {
+                    *state != start && nexts.values().any(|&count| count > 0.0)
+                })
+                .map(|(state, _)| state.clone()),
+        );
+        let index = states
+            .iter()
+            .enumerate()
+            .map(|(i, state)| (state, i))
+            .collect::<HashMap<_, _>>();
+        let mut compact = CompactChain {
+            offsets: vec![0],
+            tokens: Vec::new(),
+            targets: Vec::new(),
+            probabilities: Vec::new(),
+            rng: self.rng.clone(),
+        };
+        let stuck = self.stuck_states();
+        for state in &states {
+            if let Some(nexts) = self.map.get(state) {
+                let nexts = nexts
+                    .iter()
+                    .filter(|&(next, &count)| {
+                        count > 0.0 && (next.is_none() || !stuck.contains(&next_state(state, next)))
+                    })
+                    .collect::<Vec<_>>();
+                let total = nexts.iter().map(|&(_, &count)| count).sum::<f64>();
+                let mut acc = 0.0;
+                for (next, &count) in nexts {
+                    acc += count;
+                    compact.probabilities.push((acc / total) as f32);
+                    compact
+                        .tokens
+                        .push(next.clone().map(|token| self.surface_form(token)));
+                    compact.targets.push(match *next {
+                        Some(_) => index.get(&next_state(state, next)).cloned(),
+                        None => None,
+                    });
+                }
+            }
+            compact.offsets.push(compact.tokens.len());
+        }
+        compact
+    }
+
+    /// Begins a generation that can be carried out piecemeal with `advance`, for producing output
+    /// a few tokens at a time across separate calls, such as the requests of a service.
+    pub fn begin_generation(&self) -> GenerationState<T> {
+        GenerationState {
+            state: vec![None; self.order],
+            finished: false,
+        }
+    }
+
+    /// Advances a generation begun with `begin_generation` by one step, returning the next token,
+    /// or `None` once the end of a sentence is reached. Draining a generation this way produces
+    /// the same kind of output as `generate`, but it can be paused between any two tokens, and the
+    /// generation state can be stored in the meantime. The state should only be advanced with the
+    /// chain it came from, or an unchanged copy of it; with any other chain, the generation ends
+    /// as soon as it reaches a state that chain does not know.
+    pub fn advance<R: Rng>(&self, state: &mut GenerationState<T>, rng: &mut R) -> Option<T> {
+        if state.finished {
+            return None;
+        }
+        let next = self.map.get(&state.state).and_then(|nexts| {
+            weighted_choice(nexts.iter().map(|(next, &count)| (next, count)), rng)
+        });
+        match next {
+            Some(Some(token)) => {
+                state.state = next_state(&state.state, &Some(token.clone()));
+                Some(self.surface_form(token))
+            }
+            _ => {
+                state.finished = true;
+                None
+            }
+        }
+    }
+
+    /// Finds the states that generation can never escape once it enters them, as they can reach
+    /// neither the end of a sentence nor anything but each other. Each is given as the `order`
+    /// tokens it is made of, and the result is empty for a healthy chain. Precisely, these are
+    /// the members of every strongly connected component of the transition graph that has no
+    /// transitions leaving it, be it to the end of a sentence, to another component, or to a state
+    /// the chain does not know, such as a token that only ever followed itself. A state without
+    /// any successors is not absorbing, since generation simply stops there. Training alone never
+    /// creates absorbing states, as every sequence fed into the chain ends, but evicting or
+    /// removing transitions can.
+    pub fn absorbing_states(&self) -> Vec<Vec<Option<T>>> {
+        let states = self.map.keys().collect::<Vec<_>>();
+        let index = states
+            .iter()
+            .enumerate()
+            .map(|(i, &state)| (state, i))
+            .collect::<HashMap<_, _>>();
+        let mut edges = vec![Vec::new(); states.len()];
+        let mut reversed = vec![Vec::new(); states.len()];
+        let mut closed = vec![true; states.len()];
+        for (i, &state) in states.iter().enumerate() {
+            let nexts = self.possible_successors(state);
+            closed[i] = !nexts.is_empty();
+            for next in nexts {
+                match next.as_ref().and(index.get(&next_state(state, &next))) {
+                    Some(&j) => {
+                        edges[i].push(j);
+                        reversed[j].push(i);
+                    }
+                    None => closed[i] = false,
+                }
+            }
+        }
+
+        // Kosaraju's algorithm: order the states by when a depth-first search finishes with them,
+        // then collect the components with a search over the reversed graph in the reverse order.
+        let mut finished = Vec::new();
+        let mut visited = vec![false; states.len()];
+        for root in 0..states.len() {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            let mut stack = vec![(root, 0)];
+            while let Some(&mut (i, ref mut edge)) = stack.last_mut() {
+                match edges[i].get(*edge) {
+                    Some(&j) => {
+                        *edge += 1;
+                        if !visited[j] {
+                            visited[j] = true;
+                            stack.push((j, 0));
+                        }
+                    }
+                    None => {
+                        finished.push(i);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        let mut component = vec![usize::MAX; states.len()];
+        let mut components = 0;
+        for &root in finished.iter().rev() {
+            if component[root] != usize::MAX {
+                continue;
+            }
+            component[root] = components;
+            let mut stack = vec![root];
+            while let Some(i) = stack.pop() {
+                for &j in &reversed[i] {
+                    if component[j] == usize::MAX {
+                        component[j] = components;
+                        stack.push(j);
+                    }
+                }
+            }
+            components += 1;
+        }
+
+        let mut escapable = vec![false; components];
+        for i in 0..states.len() {
+            if !closed[i] || edges[i].iter().any(|&j| component[j] != component[i]) {
+                escapable[component[i]] = true;
+            }
+        }
+        states
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| !escapable[component[i]])
+            .map(|(_, state)| state.clone())
+            .collect()
+    }
+
+    /// Computes the probability that a generation reaches the end of a sentence within `n`
+    /// transitions, the first of which leaves the beginning of the sentence, so that generating
+    /// `k` tokens takes `k + 1` of them. This is found exactly by pushing the distribution over
+    /// states forward one transition at a time, collecting the probability that moves to the end of
+    /// a sentence, so it costs `O(ne)` in the number of transitions `e`. Generations that hit a
+    /// dead end stop without reaching the end of a sentence, so they are never counted. As `n`
+    /// grows, this approaches the probability of ever finishing a sentence, which is one unless
+    /// generation can run into a dead end or get stuck in one of the `absorbing_states`.
+    pub fn termination_probability(&self, n: usize) -> f64 {
+        let mut dist = HashMap::new();
+        dist.insert(vec![None; self.order], 1.0);
+        let mut terminated = 0.0;
+        for _ in 0..n {
+            let mut next_dist = HashMap::new();
+            for (state, mass) in dist {
+                let nexts = match self.map.get(&state) {
+                    Some(nexts) => nexts,
+                    None => continue,
+                };
+                let total = nexts.values().filter(|&&count| count > 0.0).sum::<f64>();
+                for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                    let p = mass * count / total;
+                    match *next {
+                        Some(_) => *next_dist.entry(next_state(&state, next)).or_insert(0.0) += p,
+                        None => terminated += p,
+                    }
+                }
+            }
+            dist = next_dist;
+        }
+        terminated
+    }
+
+    /// Summarizes how predictable the chain is as a single number between zero and one, where one
+    /// means that every state has a single possible successor and zero that every state picks
+    /// uniformly among its successors. Precisely, this is `1 - Σ w(s) · H(s) / log2(n(s))`: for
+    /// every state `s` with `n(s) > 1` possible successors, the entropy `H(s)` of its successors is
+    /// normalized by the greatest entropy that many successors could have, and the average of
+    /// those is weighted by how often each state was visited, `w(s)`, which is its share of all of
+    /// the counts in the chain. States with a single successor contribute no uncertainty. An
+    /// empty chain is perfectly predictable.
+    pub fn predictability(&self) -> f64 {
+        let totals = self
+            .map
+            .values()
+            .map(|nexts| nexts.values().filter(|&&count| count > 0.0).sum::<f64>())
+            .collect::<Vec<_>>();
+        let total = totals.iter().sum::<f64>();
+        if total <= 0.0 {
+            return 1.0;
+        }
+        let uncertainty = self
+            .map
+            .values()
+            .zip(totals)
+            .map(|(nexts, visits)| {
+                let n = nexts.values().filter(|&&count| count > 0.0).count();
+                if n > 1 {
+                    visits / total * nexts.entropy() / (n as f64).log2()
+                } else {
+                    0.0
+                }
+            })
+            .sum::<f64>();
+        1.0 - uncertainty
+    }
+
+    /// Finds a shortest sequence of tokens leading from `from` to `to` through the transitions of
+    /// the chain, both included, or returns `None` if `to` cannot be reached from `from`. This is
+    /// a breadth-first search over the states of the chain, so it only considers whether each
+    /// transition was ever seen, regardless of how likely it is: the path found may well be one
+    /// the chain would hardly ever generate. In chains of higher order, the search starts from
+    /// every state ending with `from`. The path never crosses the end of a sentence.
+    pub fn shortest_path(&self, from: &T, to: &T) -> Option<Vec<T>> {
+        let from = Some(from.clone());
+        let to = Some(to.clone());
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::new();
+        for state in self.map.keys().filter(|state| state.last() == Some(&from)) {
+            parents.insert(state.clone(), None);
+            queue.push_back(state.clone());
+        }
+        let mut found = if from == to {
+            queue.front().cloned()
+        } else {
+            None
+        };
+        while found.is_none() {
+            let state = queue.pop_front()?;
+            for next in self.possible_successors(&state) {
+                if next.is_none() {
+                    continue;
+                }
+                let next_state = next_state(&state, &next);
+                if parents.contains_key(&next_state) {
+                    continue;
+                }
+                parents.insert(next_state.clone(), Some(state.clone()));
+                if next == to {
+                    found = Some(next_state);
+                    break;
+                }
+                queue.push_back(next_state);
+            }
+        }
+        let mut path = Vec::new();
+        let mut state = found;
+        while let Some(current) = state {
+            path.extend(current.last().cloned().flatten());
+            state = parents.remove(&current).flatten();
+        }
+        path.reverse();
+        Some(self.surface(path))
+    }
+
+    /// Determines whether `a` can lead to `b` and `b` back to `a` through the transitions of the
+    /// chain, that is, whether the two tokens are in the same strongly connected component of its
+    /// graph, such as a loop the chain can go around. This runs the two breadth-first searches
+    /// of `shortest_path`, so it takes `O(s + e)` time in the number of states `s` and transitions
+    /// `e`. Like there, paths never cross the end of a sentence, and a token that was seen is
+    /// always mutually reachable with itself.
+    pub fn mutually_reachable(&self, a: &T, b: &T) -> bool {
+        self.shortest_path(a, b).is_some() && self.shortest_path(b, a).is_some()
+    }
+
+    /// Computes how many states of the chain have each number of distinct successors, the end of a
+    /// sentence counting as one. A chain dominated by states with a single successor mostly
+    /// reproduces its training data, while one where states have many is freer to recombine it.
+    /// States without any successors, such as the start of an empty chain, are left out.
+    pub fn out_degree_histogram(&self) -> HashMap<usize, usize> {
+        let mut histogram = HashMap::new();
+        for nexts in self.map.values().filter(|nexts| !nexts.is_empty()) {
+            *histogram.entry(nexts.len()).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Computes the fraction of all possible pairs of tokens that the chain has seen directly
+    /// follow each other: the number of distinct such pairs divided by the square of the number
+    /// of distinct tokens. This is a quick measure of how sparse the chain is, close to one for a
+    /// richly connected chain and close to zero for one that mostly strings its tokens along in a
+    /// single order. The beginning and end of a sentence are not tokens, so transitions from or to
+    /// them do not count. In chains of higher order, a pair counts if any state ending with its
+    /// first token has its second as a successor. An empty chain gives zero.
+    pub fn bigram_density(&self) -> f64 {
+        let vocabulary = self.tokens().len();
+        if vocabulary == 0 {
+            return 0.0;
+        }
+        let pairs = self
+            .map
+            .iter()
+            .filter_map(|(state, nexts)| state.last().cloned().flatten().map(|last| (last, nexts)))
+            .flat_map(|(last, nexts)| {
+                nexts
+                    .iter()
+                    .filter(|&(next, &count)| next.is_some() && count > 0.0)
+                    .map(move |(next, _)| (last.clone(), next.clone()))
+            })
+            .collect::<HashSet<_>>();
+        pairs.len() as f64 / (vocabulary * vocabulary) as f64
+    }
+
+    /// Computes the natural logarithm of the probability that the chain generates exactly the
+    /// given sequence of tokens, from the start of a sentence through to its end. This returns
+    /// negative infinity if any transition in the sequence was never observed during training.
+    pub fn sequence_log_probability(&self, tokens: &[T]) -> f64 {
+        self.smoothed_log_probability(&self.resolve_unknown(tokens), 0.0)
+    }
+
+    /// Determines whether or not the chain supports the given sequence of tokens from the start of
+    /// a sentence through to its end, that is, whether every transition along the way was seen
+    /// during training. Anything generated from the chain passes this check, so it cannot tell
+    /// novel output from copied output on its own. What it does flag is a sequence made up
+    /// entirely out of observed transitions, which for a chain of high enough order often means
+    /// a sentence lifted straight from the training data. The check is only as strict as the
+    /// order of the chain, though: a sequence can pass by stitching together transitions from
+    /// several training sequences without matching any one of them. Empty sequences are never
+    /// supported, since they are never fed into the chain.
+    pub fn contains_sequence(&self, tokens: &[T]) -> bool {
+        !tokens.is_empty()
+            && self.wrap(tokens).windows(self.order + 1).all(|p| {
+                self.map
+                    .get(&p[0..self.order])
+                    .and_then(|nexts| nexts.get(&p[self.order]))
+                    .is_some_and(|&count| count > 0.0)
+            })
+    }
+
+    /// Measures how novel a sequence of tokens is with respect to the training data: the fraction
+    /// of its pairs of consecutive tokens that the chain has never seen directly follow each
+    /// other. Zero means that every step of the sequence was copied from the training data, and
+    /// one that none was, so this complements `contains_sequence` by measuring how much a
+    /// generation recombines rather than merely whether it does. The beginning and end of a
+    /// sentence are left out, and a sequence of fewer than two tokens gives zero. In chains of
+    /// higher order, a pair counts as seen if any state ending with its first token has its
+    /// second as a successor.
+    pub fn novelty_score(&self, tokens: &[T]) -> f64 {
+        if tokens.len() < 2 {
+            return 0.0;
+        }
+        let unseen = tokens
+            .windows(2)
+            .filter(|p| !self.has_transition(&self.canonical(&p[0]), &self.canonical(&p[1])))
+            .count();
+        unseen as f64 / (tokens.len() - 1) as f64
+    }
+
+    /// Computes the probability that the chain generates exactly the given sequence of tokens.
+    /// This returns zero if any transition in the sequence was never observed during training.
+    pub fn sequence_probability(&self, tokens: &[T]) -> f64 {
+        self.sequence_log_probability(tokens).exp()
+    }
+
+    /// Computes the per-token perplexity of the chain over a held-out set of sentences. Each
+    /// sentence is scored from its start through to its end, so the end of every sentence counts
+    /// as a predicted token. Lower is better, and a perfect model has a perplexity of `1.0`. No
+    /// smoothing is applied: a single unseen transition makes the perplexity infinite. Use
+    /// `smoothed_perplexity` to score text containing unseen transitions.
+    pub fn perplexity(&self, sentences: &[Vec<T>]) -> f64 {
+        self.smoothed_perplexity(sentences, 0.0)
+    }
+
+    /// Computes the per-token perplexity of the chain over a held-out set of sentences using
+    /// additive smoothing. Every transition is treated as if it had been observed `alpha` more
+    /// times than it actually was, over every token in the chain plus the end of a sentence. This
+    /// gives unseen transitions, and states that were never seen at all, a small but nonzero
+    /// probability. An `alpha` of zero disables smoothing. Empty sentences are skipped, just as
+    /// `feed` ignores them, and a set without any tokens has a perplexity of `1.0`.
+    pub fn smoothed_perplexity(&self, sentences: &[Vec<T>], alpha: f64) -> f64 {
+        let mut log_prob = 0.0;
+        let mut count = 0;
+        for sentence in sentences
+            .iter()
+            .map(|sentence| self.resolve_unknown(sentence))
+        {
+            if sentence.is_empty() {
+                continue;
+            }
+            log_prob += self.smoothed_log_probability(&sentence, alpha);
+            count += sentence.len() + 1;
+        }
+        if count == 0 {
+            return 1.0;
+        }
+        (-log_prob / count as f64).exp()
+    }
+
+    /// Applies the unknown token policy of the chain to a sequence of tokens about to be scored,
+    /// after resolving each token to its canonical token, or to the token it is a surface form of.
+    fn resolve_unknown(&self, tokens: &[T]) -> Vec<T> {
+        let known = self.tokens().into_iter().collect::<HashSet<_>>();
+        let forms = self
+            .surface_forms
+            .iter()
+            .flatten()
+            .flat_map(|(token, seen)| seen.keys().map(move |form| (form, token)))
+            .collect::<HashMap<_, _>>();
+        let tokens = tokens
+            .iter()
+            .map(|token| {
+                let token = self.canonical(token);
+                match forms.get(&token) {
+                    Some(&normalized) if !known.contains(&token) => normalized.clone(),
+                    _ => token,
+                }
+            })
+            .collect::<Vec<_>>();
+        match (self.unknown_token_policy, &self.unknown_token) {
+            (UnknownTokenPolicy::UnkState, Some(unknown)) => tokens
+                .iter()
+                .map(|token| {
+                    if known.contains(token) {
+                        token
+                    } else {
+                        unknown
+                    }
+                })
+                .cloned()
+                .collect(),
+            (UnknownTokenPolicy::Skip, _) => tokens
+                .iter()
+                .filter(|token| known.contains(token))
+                .cloned()
+                .collect(),
+            _ => tokens,
+        }
+    }
+
+    /// Computes the log probability of a sequence with additive smoothing of `alpha`.
+    fn smoothed_log_probability(&self, tokens: &[T], alpha: f64) -> f64 {
+        let outcomes = (self.tokens().len() + 1) as f64;
+        let mut toks = vec![None; self.order];
+        toks.extend(tokens.iter().map(|token| Some(token.clone())));
+        toks.push(None);
+        toks.windows(self.order + 1)
+            .map(|p| {
+                let (count, total) = match self.map.get(&p[0..self.order]) {
+                    Some(nexts) => (
+                        nexts.get(&p[self.order]).cloned().unwrap_or(0.0),
+                        nexts.values().sum::<f64>(),
+                    ),
+                    None => (0.0, 0.0),
+                };
+                let prob = (count + alpha) / (total + alpha * outcomes);
+                if prob > 0.0 {
+                    prob.ln()
+                } else {
+                    f64::NEG_INFINITY
+                }
+            })
+            .sum()
+    }
+
+    /// Walks the chain from the state `curs` until it reaches the end of a sentence, appending
+    /// every token along the way to `ret`. At each step, `pick` is given the current state, its
+    /// successors, the tokens emitted so far, and the random number generator to use, and chooses
+    /// the next token. The walk also stops early if `pick` returns `None`, and dead ends are left
+    /// to `recover`. So that the walk always ends, once it has taken as many steps as the chain
+    /// has states, and again every time that number of steps doubles, it checks whether it is
+    /// stuck among absorbing states, and if so handles the current state like a dead end. Shorter
+    /// walks never pay for the check. The tokens are returned in their most common surface
+    /// forms, if the chain tracks them.
+    fn walk<R, F>(
+        &self,
+        mut curs: Vec<Token<T>>,
+        mut ret: Vec<T>,
+        rng: &mut R,
+        mut pick: F,
+    ) -> Vec<T>
+    where
+        R: Rng,
+        F: FnMut(&[Token<T>], &HashMap<Token<T>, f64>, &[T], &mut R) -> Option<Token<T>>,
+    {
+        let mut recoveries = self.map.len();
+        let mut steps = 0;
+        let mut check_at = self.map.len().max(1);
+        loop {
+            steps += 1;
+            let mut trapped = false;
+            if steps == check_at {
+                check_at *= 2;
+                trapped = self.absorbing_states().contains(&curs);
+            }
+            let nexts = match self.map.get(&curs) {
+                Some(nexts) if !trapped && nexts.values().any(|&count| count > 0.0) => nexts,
+                _ => match self.recover(&curs, rng, &mut recoveries) {
+                    Some(state) => {
+                        curs = state;
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            let next = match pick(&curs, nexts, &ret, rng) {
+                Some(next) => next,
+                None => break,
+            };
+            curs = next_state(&curs, &next);
+            match next {
+                Some(next) => ret.push(next),
+                None => break,
+            }
+        }
+        self.surface(ret)
+    }
+
+    /// Picks the state to carry on from after reaching the dead end `curs`, according to the dead
+    /// end policy of the chain, or returns `None` if generation should terminate instead. Every
+    /// recovery uses up one of the given number of `recoveries`.
+    fn recover<R: Rng>(
+        &self,
+        curs: &[Token<T>],
+        rng: &mut R,
+        recoveries: &mut usize,
+    ) -> Option<Vec<Token<T>>> {
+        if *recoveries == 0 {
+            return None;
+        }
+        *recoveries -= 1;
+        let start = vec![None; self.order];
+        match self.dead_end_policy {
+            DeadEndPolicy::Terminate => None,
+            DeadEndPolicy::RestartFromStart => {
+                if curs == &start[..] || self.is_empty() {
+                    None
+                } else {
+                    Some(start)
+                }
+            }
+            DeadEndPolicy::JumpRandom => {
+                let live = self
+                    .map
+                    .iter()
+                    .filter(|&(_, nexts)| nexts.values().any(|&count| count > 0.0))
+                    .map(|(state, _)| state)
+                    .collect::<Vec<_>>();
+                if live.is_empty() {
+                    return None;
+                }
+                Some(live[rng.gen_range(0..live.len())].clone())
+            }
+        }
+    }
+
+    /// Generates a collection of tokens from the chain with the given random number generator.
+    fn generate_with<R: Rng>(&self, rng: &mut R) -> Vec<T> {
+        self.walk(
+            vec![None; self.order],
+            Vec::new(),
+            rng,
+            |_, nexts, _, rng| Some(nexts.next(rng)),
+        )
+    }
+
+    /// Surrounds a collection of tokens with the sentence boundaries they are fed with.
+    fn wrap(&self, tokens: &[T]) -> Vec<Token<T>> {
+        let mut toks = vec![None; self.order];
+        toks.extend(tokens.iter().map(|token| Some(self.canonical(token))));
+        toks.push(None);
+        toks
+    }
+
+    /// Resolves a token to the canonical token it is fed as, which is itself unless it is an
+    /// alias.
+    fn canonical(&self, token: &T) -> T {
+        self.aliases
+            .as_ref()
+            .and_then(|aliases| aliases.get(token))
+            .unwrap_or(token)
+            .clone()
+    }
+
+    /// Replaces each token with the surface form it was most often seen as, if the chain tracks
+    /// surface forms. Tokens without any recorded surface forms are left as they are.
+    fn surface(&self, tokens: Vec<T>) -> Vec<T> {
+        if self.surface_forms.is_none() {
+            return tokens;
+        }
+        tokens
+            .into_iter()
+            .map(|token| self.surface_form(token))
+            .collect()
+    }
+
+    /// Replaces a single token with the surface form it was most often seen as, if any.
+    fn surface_form(&self, token: T) -> T {
+        self.surface_forms
+            .as_ref()
+            .and_then(|forms| forms.get(&token))
+            .and_then(|seen| seen.iter().max_by_key(|&(_, &count)| count))
+            .map(|(form, _)| form.clone())
+            .unwrap_or(token)
+    }
+
+    /// Indexes the transitions of the chain in reverse, leaving out those to the end of a sentence
+    /// and those that were never actually seen.
+    fn predecessors(&self) -> Predecessors<T> {
+        let mut predecessors = HashMap::new();
+        for (state, nexts) in &self.map {
+            for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                if next.is_some() {
+                    predecessors
+                        .entry(next_state(state, next))
+                        .or_insert_with(Vec::new)
+                        .push((state.clone(), count));
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// Computes, for every state, the fewest transitions needed to reach the end of a sentence.
+    /// States from which the end of a sentence cannot be reached are left out, and so are
+    /// transitions that can never be taken because their weight is not positive.
+    fn distances_to_end(&self) -> HashMap<Vec<Token<T>>, usize> {
+        self.distances_to(&None)
+    }
+
+    /// Computes, for every state, the fewest transitions needed to emit `target`, which may be the
+    /// end of a sentence, counting the transition emitting it. States from which it cannot be
+    /// reached are left out, as in `distances_to_end`.
+    fn distances_to(&self, target: &Token<T>) -> HashMap<Vec<Token<T>>, usize> {
+        let mut preceding = HashMap::new();
+        let mut queue = VecDeque::new();
+        for (state, nexts) in &self.map {
+            for (next, _) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                if next == target {
+                    queue.push_back((state.clone(), 1));
+                }
+                if next.is_some() {
+                    preceding
+                        .entry(next_state(state, next))
+                        .or_insert_with(Vec::new)
+                        .push(state);
+                }
+            }
+        }
+        let mut distances = HashMap::new();
+        while let Some((state, distance)) = queue.pop_front() {
+            if distances.contains_key(&state) {
+                continue;
+            }
+            if let Some(prevs) = preceding.get(&state) {
+                for &prev in prevs {
+                    queue.push_back((prev.clone(), distance + 1));
+                }
+            }
+            distances.insert(state, distance);
+        }
+        distances
+    }
+
+    /// Gets the random number generator to use for a single generation. This holds on to the
+    /// generator of a seeded chain until it is dropped.
+    fn rng(&self) -> RngHandle<'_> {
+        self.rng.handle()
+    }
+
+    /// Adds `count` to the transition from `state` to `next`, creating it if needed.
+    fn reinforce(&mut self, state: &[Token<T>], next: &Token<T>, count: f64) {
+        self.map
+            .entry(state.to_vec())
+            .or_default()
+            .add(next.clone(), count);
+        if let Some(ref mut streaming) = self.streaming {
+            streaming.touch(state, next);
+        }
+    }
+
+    /// Subtracts `count` from the transition from `state` to `next`, if the chain has it. The
+    /// transition is removed once nothing is left of it, and so is its state if that leaves the
+    /// state without successors.
+    fn weaken(&mut self, state: &[Token<T>], next: &Token<T>, count: f64) {
+        let nexts = match self.map.get_mut(state) {
+            Some(nexts) => nexts,
+            None => return,
+        };
+        let left = match nexts.get_mut(next) {
+            Some(existing) => {
+                *existing -= count;
+                *existing
+            }
+            None => return,
+        };
+        if left > 1e-9 {
+            return;
+        }
+        nexts.remove(next);
+        if nexts.is_empty() && state.iter().any(Option::is_some) {
+            self.map.remove(state);
+        }
+        if let Some(ref mut streaming) = self.streaming {
+            if let Some(stamp) = streaming.stamps.remove(&(state.to_vec(), next.clone())) {
+                streaming.recency.remove(&stamp);
+            }
+        }
+    }
+
+    /// Evicts the least recently reinforced transitions until the chain is within the budget of
+    /// its streaming mode, if it is in streaming mode. This only costs as much as the evictions
+    /// themselves, so feeding a chain stays linear in what it is fed.
+    fn evict(&mut self) {
+        let streaming = match self.streaming {
+            Some(ref mut streaming) => streaming,
+            None => return,
+        };
+        let start = vec![None; self.order];
+        while streaming.stamps.len() > streaming.max_transitions {
+            let (state, next) = match streaming.recency.pop_first() {
+                Some((_, key)) => key,
+                None => break,
+            };
+            streaming.stamps.remove(&(state.clone(), next.clone()));
+            if let Some(nexts) = self.map.get_mut(&state) {
+                nexts.remove(&next);
+                if nexts.is_empty() && state != start {
+                    self.map.remove(&state);
+                }
+            }
+        }
+    }
+
+    /// Drops the streaming stamps of transitions that are no longer in the chain, after removing
+    /// transitions in bulk, so that the stamps keep counting the transitions of the chain.
+    fn forget_stale_stamps(&mut self) {
+        if let Some(ref mut streaming) = self.streaming {
+            let map = &self.map;
+            streaming.stamps.retain(|(state, next), _| {
+                map.get(state).is_some_and(|nexts| nexts.contains_key(next))
+            });
+            let stamps = &streaming.stamps;
+            streaming.recency.retain(|_, key| stamps.contains_key(key));
+        }
+    }
+
+    /// Removes the given tokens from the chain, along with every transition into or out of them,
+    /// and then prunes any states that this leaves without a way to the end of a sentence.
+    fn remove_tokens(&mut self, removed: &HashSet<T>) {
+        let is_removed =
+            |token: &Token<T>| token.as_ref().is_some_and(|token| removed.contains(token));
+        self.map.retain(|state, _| !state.iter().any(is_removed));
+        for nexts in self.map.values_mut() {
+            nexts.retain(|next, _| !is_removed(next));
+        }
+        self.prune_dead_ends();
+    }
+
+    /// Removes every state from which the end of a sentence cannot be reached, other than the
+    /// beginning of a sentence, along with every transition leading into it. This covers states
+    /// left without successors as well as cycles without a way out, either of which would
+    /// otherwise leave generation stuck.
+    fn prune_dead_ends(&mut self) {
+        let start = vec![None; self.order];
+        let alive = self.distances_to_end();
+        self.map
+            .retain(|state, _| *state == start || alive.contains_key(state));
+        for (state, nexts) in self.map.iter_mut() {
+            nexts.retain(|next, _| next.is_none() || alive.contains_key(&next_state(state, next)));
+        }
+        self.forget_stale_stamps();
+    }
+
+    /// Finds the states from which generation can never stop, be it at the end of a sentence or
+    /// at a dead end, because every way on from them leads into a cycle without a way out.
+    fn stuck_states(&self) -> HashSet<Vec<Token<T>>> {
+        let predecessors = self.predecessors();
+        let mut stack = self
+            .map
+            .keys()
+            .chain(predecessors.keys())
+            .filter(|&state| {
+                let nexts = self.possible_successors(state);
+                nexts.is_empty() || nexts.contains(&None)
+            })
+            .collect::<Vec<_>>();
+        let mut stoppable = stack.iter().cloned().collect::<HashSet<_>>();
+        while let Some(state) = stack.pop() {
+            for (prev, _) in predecessors.get(state).into_iter().flatten() {
+                if stoppable.insert(prev) {
+                    stack.push(prev);
+                }
+            }
+        }
+        self.map
+            .keys()
+            .filter(|&state| !stoppable.contains(state))
+            .cloned()
+            .collect()
+    }
+
+    /// Lists the successors of a state that can actually be picked, that is, those with a positive
+    /// count.
+    fn possible_successors(&self, state: &[Token<T>]) -> Vec<Token<T>> {
+        self.map
+            .get(state)
+            .into_iter()
+            .flatten()
+            .filter(|&(_, &count)| count > 0.0)
+            .map(|(next, _)| next.clone())
+            .collect()
+    }
+
+    /// Collects the successors of a token along with their counts. In chains of higher order, this
+    /// combines the successors of every state that ends with the token.
+    fn successors(&self, token: &T) -> HashMap<Token<T>, f64> {
+        let token = Some(token.clone());
+        if self.order == 1 {
+            return self.map.get(&vec![token]).cloned().unwrap_or_default();
+        }
+        let mut successors = HashMap::new();
+        for (state, nexts) in &self.map {
+            if state.last() == Some(&token) {
+                for (next, &count) in nexts {
+                    successors.add(next.clone(), count);
+                }
+            }
+        }
+        successors
+    }
+
+    /// Collects every distinct token known to the chain. The tokens are listed in the same order
+    /// every time for as long as the chain is left unchanged.
+    fn tokens(&self) -> Vec<&T> {
+        let mut seen = HashSet::new();
+        self.map
+            .iter()
+            .flat_map(|(state, nexts)| state.iter().chain(nexts.keys()))
+            .filter_map(|token| token.as_ref())
+            .filter(|&token| seen.insert(token))
+            .collect()
+    }
+
+    /// Lists the tokens that ended sentences, along with how many times each one did, from the
+    /// most to the least common.
+    pub fn end_token_distribution(&self) -> Vec<(T, f64)> {
+        let mut ends: HashMap<&T, f64> = HashMap::new();
+        for (state, nexts) in &self.map {
+            if let (Some(Some(token)), Some(&count)) = (state.last(), nexts.get(&None)) {
+                *ends.entry(token).or_insert(0.0) += count;
+            }
+        }
+        let mut ends = ends
+            .into_iter()
+            .map(|(token, count)| (token.clone(), count))
+            .collect::<Vec<_>>();
+        ends.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ends
+    }
+
+    /// Removes every token that was seen fewer than `min` or more than `max` times, counting each
+    /// time the token followed something else. This drops rare noise and overly common filler
+    /// words in a single pass. Every transition into or out of a removed token is dropped, along
+    /// with any state from which the end of a sentence can no longer be reached and, in turn,
+    /// every transition leading into such a state. The beginning and end of a sentence are never
+    /// removed.
+    pub fn filter_by_frequency(&mut self, min: usize, max: usize) -> &mut Chain<T> {
+        let mut incoming = HashMap::new();
+        for nexts in self.map.values() {
+            for (next, &count) in nexts {
+                if let Some(ref token) = *next {
+                    *incoming.entry(token).or_insert(0.0) += count;
+                }
+            }
+        }
+        let removed = self
+            .tokens()
+            .into_iter()
+            .filter(|token| {
+                let count = incoming.get(token).cloned().unwrap_or(0.0);
+                count < min as f64 || count > max as f64
+            })
+            .cloned()
+            .collect::<HashSet<_>>();
+        self.remove_tokens(&removed);
+        self
+    }
+
+    /// Forgets everything the chain learned about what follows `token`, so that its continuations
+    /// can be retrained from scratch. The state of `token` is kept, but left without successors,
+    /// and in chains of higher order, so is every state ending with it. Transitions leading into
+    /// `token` are left as they are, so until it is fed new continuations, generation reaching it
+    /// hits a dead end and is handled by the dead end policy. Nothing happens if the token was
+    /// never seen.
+    pub fn clear_state(&mut self, token: &T) -> &mut Chain<T> {
+        let token = Some(token.clone());
+        for (state, nexts) in self.map.iter_mut() {
+            if state.last() != Some(&token) {
+                continue;
+            }
+            if let Some(ref mut streaming) = self.streaming {
+                for next in nexts.keys() {
+                    if let Some(stamp) = streaming.stamps.remove(&(state.clone(), next.clone())) {
+                        streaming.recency.remove(&stamp);
+                    }
+                }
+            }
+            nexts.clear();
+        }
+        self
+    }
+
+    /// Trims every state down to its `n` most frequently seen successors, dropping all of the
+    /// others, which shrinks the chain a lot while keeping its dominant behavior. The odds of the
+    /// remaining successors are scaled up to make up for the dropped ones. Where keeping only the
+    /// top `n` would leave a state unable to ever reach the end of a sentence, it also keeps the
+    /// one successor that gets there the fastest, so trimming never cuts generation off from the
+    /// end; with `n` set to zero, that is the only successor each state keeps. States that can then
+    /// no longer be reached from the beginning of a sentence are removed. Since only the most
+    /// likely continuations survive, the output becomes much less varied, and with `n` set to one
+    /// the chain can only ever generate a single sequence.
+    pub fn keep_top_successors(&mut self, n: usize) -> &mut Chain<T> {
+        let original = self.map.clone();
+        let distances = self.distances_to_end();
+        for nexts in self.map.values_mut() {
+            let kept = sorted_by_count(nexts)
+                .into_iter()
+                .filter(|&(_, count)| count > 0.0)
+                .take(n)
+                .map(|(next, _)| next.clone())
+                .collect::<HashSet<_>>();
+            nexts.retain(|next, _| kept.contains(next));
+        }
+        let alive = self.distances_to_end();
+        for (state, distance) in &distances {
+            if alive.contains_key(state) {
+                continue;
+            }
+            let fastest = original[state]
+                .iter()
+                .filter(|&(_, &count)| count > 0.0)
+                .find(|&(next, _)| {
+                    next.is_none()
+                        || distances
+                            .get(&next_state(state, next))
+                            .is_some_and(|there| there < distance)
+                });
+            if let Some((next, &count)) = fastest {
+                self.map
+                    .entry(state.clone())
+                    .or_default()
+                    .add(next.clone(), count);
+            }
+        }
+        let start = vec![None; self.order];
+        let mut reachable = HashSet::new();
+        let mut stack = vec![start];
+        while let Some(state) = stack.pop() {
+            if let Some(nexts) = self.map.get(&state) {
+                for next in nexts.keys().filter(|next| next.is_some()) {
+                    let next = next_state(&state, next);
+                    if !reachable.contains(&next) {
+                        stack.push(next);
+                    }
+                }
+            }
+            reachable.insert(state);
+        }
+        self.map.retain(|state, _| reachable.contains(state));
+        self.forget_stale_stamps();
+        self
+    }
+
+    /// Builds a new chain of the same order in which every token has been transformed by `f`.
+    /// When several distinct tokens are transformed into the same value, their states and
+    /// transitions are combined and their counts summed. The beginning and end of a sentence are
+    /// left as they are. This allows stemming, case folding, or bucketing a trained chain without
+    /// retraining it from the raw data. The lengths of the sequences fed into the chain are carried
+    /// over, and so are those of the sequences each token began, but surface forms recorded by a
+    /// case-insensitive chain are not.
+    pub fn map_states<U, F>(&self, f: F) -> Chain<U>
+    where
+        U: Chainable,
+        F: Fn(&T) -> U,
+    {
+        let mut chain = Chain::of_order(self.order);
+        for (state, nexts) in &self.map {
+            let state = state
+                .iter()
+                .map(|token| token.as_ref().map(&f))
+                .collect::<Vec<_>>();
+            let states = chain.map.entry(state).or_default();
+            for (next, &count) in nexts {
+                states.add(next.as_ref().map(&f), count);
+            }
+        }
+        chain.lengths = self.lengths.clone();
+        chain.openings = self.openings.as_ref().map(|openings| {
+            let mut mapped = HashMap::new();
+            for (token, &(weight, length)) in openings {
+                let opening = mapped.entry(f(token)).or_insert((0.0, 0.0));
+                opening.0 += weight;
+                opening.1 += length;
+            }
+            mapped
+        });
+        chain
+    }
+
+    /// Splits the chain into sub-chains, one for each distinct key that `f` assigns to its tokens.
+    /// Every state is put into the bucket of its tokens, and states whose tokens fall into
+    /// different buckets are dropped, as no sub-chain could ever reach them. The beginning of a
+    /// sentence is shared by all of the sub-chains, each getting only the transitions from it to
+    /// tokens of its own bucket. A transition that crosses from one bucket into another is routed
+    /// to the end of a sentence instead, so that its count is kept: generation from a sub-chain
+    /// then simply stops where the full chain would have moved into another bucket. Each
+    /// sub-chain is of the same order as this one and keeps its surface forms.
+    pub fn partition<K, F>(&self, f: F) -> HashMap<K, Chain<T>>
+    where
+        K: Eq + Hash,
+        F: Fn(&T) -> K,
+    {
+        let mut chains = HashMap::new();
+        for (state, nexts) in &self.map {
+            let keys = state.iter().flatten().map(&f).collect::<Vec<_>>();
+            if keys.windows(2).any(|pair| pair[0] != pair[1]) {
+                continue;
+            }
+            let last = state.last().and_then(Option::as_ref);
+            for (next, &count) in nexts {
+                let (key, next) = match (last, next) {
+                    (None, None) => continue,
+                    (None, Some(token)) => (f(token), next.clone()),
+                    (Some(last), Some(token)) => {
+                        let key = f(last);
+                        if f(token) == key {
+                            (key, next.clone())
+                        } else {
+                            (key, None)
+                        }
+                    }
+                    (Some(last), None) => (f(last), None),
+                };
+                chains
+                    .entry(key)
+                    .or_insert_with(|| {
+                        let mut chain = Chain::of_order(self.order);
+                        chain.surface_forms = self.surface_forms.clone();
+                        chain
+                    })
+                    .map
+                    .entry(state.clone())
+                    .or_default()
+                    .add(next, count);
+            }
+        }
+        chains
+    }
+
+    /// Puts the chain in streaming mode, keeping it within a memory budget of `max_transitions`
+    /// distinct transitions for never-ending training. Whenever feeding the chain takes it over
+    /// the budget, the transitions that were least recently reinforced are evicted until it fits
+    /// again. A transition is reinforced whenever it is fed into the chain, and those already in
+    /// the chain when streaming is enabled count as older than anything fed afterwards. An evicted
+    /// transition is forgotten entirely, along with its state if that state is left without
+    /// successors, so this trades completeness for bounded memory: rare phrasings fade away, and
+    /// generation may end early where an evicted state used to continue. Calling this again
+    /// changes the budget.
+    pub fn enable_streaming(&mut self, max_transitions: usize) -> &mut Chain<T> {
+        if let Some(ref mut streaming) = self.streaming {
+            streaming.max_transitions = max_transitions;
+        } else {
+            let mut streaming = Streaming {
+                max_transitions,
+                clock: 0,
+                stamps: HashMap::new(),
+                recency: BTreeMap::new(),
+            };
+            for (state, nexts) in &self.map {
+                for next in nexts.keys() {
+                    streaming.touch(state, next);
+                }
+            }
+            self.streaming = Some(streaming);
+        }
+        self.evict();
+        self
+    }
+
+    /// Takes a snapshot of the chain, which is a deep copy entirely independent of it. Training the
+    /// chain further leaves the snapshot untouched, so assigning the snapshot back to the chain
+    /// undoes everything fed into it since. This is the same as `clone`, and is meant for quick
+    /// in-memory experiments where saving the chain to disk would be overkill.
+    ///
+    /// ```
+    /// use markov::Chain;
+    ///
+    /// let mut chain = Chain::new();
+    /// chain.feed_str("I like cats");
+    /// let checkpoint = chain.checkpoint();
+    /// chain.feed_str("I like dogs");
+    /// chain = checkpoint;
+    /// assert_eq!(chain.generate_str(), "I like cats");
+    /// ```
+    pub fn checkpoint(&self) -> Chain<T> {
+        self.clone()
+    }
+
+    /// Produces an infinite iterator of generated token collections.
+    pub fn iter(&self) -> InfiniteChainIterator<'_, T> {
+        InfiniteChainIterator { chain: self }
+    }
+
+    /// Produces an iterator for the specified number of generated token collections.
+    pub fn iter_for(&self, size: usize) -> SizedChainIterator<'_, T> {
+        SizedChainIterator { chain: self, size }
+    }
+
+    /// Produces a never-ending random walk over the chain, yielding one token at a time. Whenever
+    /// the walk reaches the end of a sentence, or a state with no successors, it starts over from
+    /// the beginning of a new sentence, and it does the same once it is caught in one of the
+    /// `absorbing_states`, which it checks for the way `generate` does. The walk only ends if no
+    /// sentence can begin with a token, as in an empty chain or one that has only ever seen empty
+    /// sentences. Use `sentences` on the walk to group its tokens into sentences.
+    pub fn random_walk(&self) -> RandomWalkIterator<'_, T> {
+        RandomWalkIterator {
+            chain: self,
+            curs: vec![None; self.order],
+            steps: 0,
+            check_at: self.map.len().max(1),
+        }
+    }
+
+    /// Produces a generator that spreads its output over as much of the chain as it can, for
+    /// building a varied corpus of outputs. Unlike calling `generate` repeatedly, the generator
+    /// remembers across calls how often it took each transition, and favors the ones it used
+    /// least, so successive generations wander into different parts of the chain.
+    pub fn exploring_generator(&self) -> ExploringGenerator<'_, T> {
+        ExploringGenerator {
+            chain: self,
+            uses: HashMap::new(),
+        }
+    }
+
+    /// Create a graph using `petgraph` from the markov chain.
+    #[cfg(feature = "graph")]
+    pub fn graph(&self) -> Graph<Vec<Token<T>>, f64> {
+        let mut graph = Graph::new();
+
+        // Create all possible node and store indices into hashmap.
+        let state_map = self
+            .map
+            .iter()
+            .flat_map(|(state, nexts)| {
+                let mut states = vec![state.clone()];
+
+                let mut state = state.clone();
+                state.remove(0);
+
+                for next in nexts {
+                    let mut next_state = state.clone();
+                    next_state.push(next.0.clone());
+                    states.push(next_state);
+                }
+
+                states
+            })
+            .unique()
+            .map(|state| (state.clone(), graph.add_node(state)))
+            .collect::<HashMap<_, _>>();
+
+        // Create all edges, and add them to the graph.
+        self.map
+            .iter()
+            .flat_map(|(state, nexts)| {
+                let sum = nexts.values().sum::<f64>();
+
+                nexts
+                    .iter()
+                    .map(|(next, p)| (state.clone(), next.clone(), *p / sum))
+                    .collect::<Vec<_>>()
+            })
+            .for_each(|(state, next, p)| {
+                let mut next_state = state.clone();
+                next_state.remove(0);
+                next_state.push(next);
+
+                graph.add_edge(state_map[&state], state_map[&next_state], p);
+            });
+
+        graph
+    }
+}
+
+impl<T> Chain<T>
+where
+    T: Chainable + Ord,
+{
+    /// Finds the single most probable sentence the chain can generate: the path from the start of
+    /// a sentence to its end with the greatest product of transition probabilities. This uses no
+    /// randomness at all, so it returns the same output every time for a given chain, even across
+    /// separately built or loaded copies of it. It is found with Dijkstra's algorithm, treating
+    /// the negative log probability of each transition as its length. Whenever several paths are
+    /// equally probable, the one whose tokens come first in their natural order wins. This returns
+    /// an empty collection if the chain is empty. This requires the tokens to be `Ord`.
+    pub fn generate_most_probable(&self) -> Vec<T> {
+        let mut heap = BinaryHeap::new();
+        let mut settled = HashSet::new();
+        heap.push(Candidate {
+            cost: 0.0,
+            path: Vec::new(),
+            state: Some(vec![None; self.order]),
+        });
+        while let Some(Candidate { cost, path, state }) = heap.pop() {
+            let state = match state {
+                Some(state) => state,
+                None => return self.surface(path),
+            };
+            if !settled.insert(state.clone()) {
+                continue;
+            }
+            let nexts = match self.map.get(&state) {
+                Some(nexts) => nexts,
+                None => continue,
+            };
+            let total = nexts.values().sum::<f64>();
+            for (next, &count) in nexts {
+                if count <= 0.0 {
+                    continue;
+                }
+                let mut path = path.clone();
+                let state = next.as_ref().map(|token| {
+                    path.push(token.clone());
+                    next_state(&state, next)
+                });
+                heap.push(Candidate {
+                    cost: cost - (count / total).ln(),
+                    path,
+                    state,
+                });
+            }
+        }
+        Vec::new()
+    }
+
+    /// Generates a sentence with a beam search, which usually makes for more coherent output than
+    /// always taking the most likely successor, but without the randomness of `generate`. At each
+    /// step, every partial sentence in the beam is extended with each of its successors, and only
+    /// the `beam_width` most probable of the results, including sentences that have already
+    /// ended, are kept for the next step. This returns the most probable sentence in the beam
+    /// once all of them have ended, and sentences are never extended past `max_len` tokens, so
+    /// this returns an empty collection if none of the sentences kept can end within that length,
+    /// or if the chain is empty. Each of the at most `max_len + 1` steps takes time in proportion
+    /// to `beam_width` times the number of successors of each state, and the beam holds up to
+    /// `beam_width` sentences of up to `max_len` tokens. Whenever several sentences are equally
+    /// probable, the one whose tokens come first in their natural order wins, and with an
+    /// unbounded width, this finds the same sentence as `generate_most_probable`.
+    pub fn generate_beam(&self, beam_width: usize, max_len: usize) -> Vec<T> {
+        let mut beam = vec![Candidate {
+            cost: 0.0,
+            path: Vec::new(),
+            state: Some(vec![None; self.order]),
+        }];
+        while beam.iter().any(|candidate| candidate.state.is_some()) {
+            let mut extended = Vec::new();
+            for Candidate { cost, path, state } in beam {
+                let state = match state {
+                    Some(state) => state,
+                    None => {
+                        extended.push(Candidate { cost, path, state });
+                        continue;
+                    }
+                };
+                let nexts = match self.map.get(&state) {
+                    Some(nexts) => nexts,
+                    None => continue,
+                };
+                let total = nexts.values().sum::<f64>();
+                for (next, &count) in nexts {
+                    if count <= 0.0 || (next.is_some() && path.len() == max_len) {
+                        continue;
+                    }
+                    let mut path = path.clone();
+                    let state = next.as_ref().map(|token| {
+                        path.push(token.clone());
+                        next_state(&state, next)
+                    });
+                    extended.push(Candidate {
+                        cost: cost - (count / total).ln(),
+                        path,
+                        state,
+                    });
+                }
+            }
+            extended.sort_by(|a, b| b.cmp(a));
+            extended.truncate(beam_width);
+            beam = extended;
+        }
+        beam.into_iter()
+            .next()
+            .map(|candidate| self.surface(candidate.path))
+            .unwrap_or_default()
+    }
+
+    /// Finds the most probable path of exactly `n` tokens from the beginning of a sentence, for
+    /// generating output of a fixed length, such as a headline. The path need not reach the end
+    /// of a sentence afterward, but its probability is the product of the probabilities of its
+    /// transitions in the chain as it is, so it gets no credit for not ending. This is found with
+    /// dynamic programming, keeping the most probable path into each state after each number of
+    /// steps, which takes time in proportion to `n` times the number of transitions in the chain,
+    /// and memory for a path of up to `n` tokens for every state. Whenever several paths are
+    /// equally probable, the one whose tokens come first in their natural order wins. This returns
+    /// an empty collection if the chain has no path of `n` tokens, or if `n` is zero.
+    pub fn most_probable_path_of_length(&self, n: usize) -> Vec<T> {
+        let mut best = HashMap::new();
+        best.insert(vec![None; self.order], (0.0, Vec::new()));
+        for _ in 0..n {
+            let mut longer = HashMap::<Vec<Token<T>>, (f64, Vec<T>)>::new();
+            for (state, (log_p, path)) in &best {
+                let nexts = match self.map.get(state) {
+                    Some(nexts) => nexts,
+                    None => continue,
+                };
+                let total = nexts.values().sum::<f64>();
+                for (next, &count) in nexts {
+                    let token = match next {
+                        Some(token) if count > 0.0 => token,
+                        _ => continue,
+                    };
+                    let log_p = log_p + (count / total).ln();
+                    let mut path = path.clone();
+                    path.push(token.clone());
+                    let state = next_state(state, next);
+                    let better = longer.get(&state).is_none_or(|(best_log_p, best_path)| {
+                        log_p > *best_log_p || (log_p == *best_log_p && path < *best_path)
+                    });
+                    if better {
+                        longer.insert(state, (log_p, path));
+                    }
+                }
+            }
+            best = longer;
+        }
+        best.into_values()
+            .max_by(|a, b| a.0.total_cmp(&b.0).then_with(|| b.1.cmp(&a.1)))
+            .map(|(_, path)| self.surface(path))
+            .unwrap_or_default()
+    }
+
+    /// Finds the `n` most characteristic phrases of `length` tokens in the chain, from the most to
+    /// the least likely. A phrase is any run of tokens that follow each other through transitions
+    /// of the chain, without crossing the beginning or end of a sentence, and it is scored with
+    /// the product of the probabilities of its transitions, so the score is the probability that
+    /// the chain carries on with the rest of the phrase once it emits its first token. In chains
+    /// of higher order, a phrase may start from any state ending with its first token, and is
+    /// scored along the best of them. This is a best-first search, which stops after stepping
+    /// into a million partial phrases to stay tractable, so on large chains with long phrases the
+    /// result may be incomplete. Phrases with the same score are ordered by their tokens.
+    pub fn top_phrases(&self, length: usize, n: usize) -> Vec<(Vec<T>, f64)> {
+        let mut heap = BinaryHeap::new();
+        for state in self.map.keys() {
+            if let Some(Some(first)) = state.last() {
+                heap.push(Candidate {
+                    cost: 0.0,
+                    path: vec![first.clone()],
+                    state: Some(state.clone()),
+                });
+            }
+        }
+        let mut phrases = Vec::new();
+        let mut found = HashSet::new();
+        let mut budget = 1_000_000usize;
+        while phrases.len() < n && length > 0 && budget > 0 {
+            let Candidate { cost, path, state } = match heap.pop() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            if path.len() == length {
+                if found.insert(path.clone()) {
+                    phrases.push((self.surface(path), (-cost).exp()));
+                }
+                continue;
+            }
+            budget -= 1;
+            let state = state.expect("partial phrases always have a state");
+            let nexts = match self.map.get(&state) {
+                Some(nexts) => nexts,
+                None => continue,
+            };
+            let total = nexts.values().filter(|&&count| count > 0.0).sum::<f64>();
+            for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                if let Some(ref token) = *next {
+                    let mut path = path.clone();
+                    path.push(token.clone());
+                    heap.push(Candidate {
+                        cost: cost - (count / total).ln(),
+                        path,
+                        state: Some(next_state(&state, next)),
+                    });
+                }
+            }
+        }
+        phrases
+    }
+
+    /// Lists every token known to the chain in its natural order. Unlike iterating over the chain
+    /// itself, this gives the same, predictable order every time. This requires the tokens to be
+    /// `Ord`.
+    pub fn sorted_vocabulary(&self) -> Vec<T> {
+        let mut tokens = self.tokens().into_iter().cloned().collect::<Vec<_>>();
+        tokens.sort();
+        tokens
+    }
+}
+
+impl<T> Chain<T>
+where
+    T: Chainable + fmt::Display,
+{
+    /// Writes the transitions of the chain as tab-separated values, one `from\tto\tcount` line
+    /// per transition, where `from` is the `order` tokens of the state, each in its own column.
+    /// The beginning and end of a sentence are written as `\N`, and backslashes, tabs, and line
+    /// breaks within tokens are escaped as `\\`, `\t`, `\n`, and `\r`. The first line is
+    /// `\order\t` followed by the order of the chain, and a state left without successors, such as
+    /// by `clear_state`, gets a line of its own with just its `order` tokens. The other lines are
+    /// sorted, so the output of an unchanged chain is always the same. Only the states and
+    /// transitions are written, not any of the other options or statistics of the chain. The
+    /// output can be read back with `load_tsv`.
+    pub fn save_tsv<W: Write>(&self, w: &mut W) -> Result<()> {
+        let field = |token: &Token<T>| match *token {
+            Some(ref token) => escape_tsv(&token.to_string()),
+            None => "\\N".to_owned(),
+        };
+        writeln!(w, "\\order\t{}", self.order)?;
+        let start = vec![None; self.order];
+        let mut lines = Vec::new();
+        for (state, nexts) in &self.map {
+            if nexts.is_empty() && *state != start {
+                lines.push(state.iter().map(field).collect::<Vec<_>>().join("\t"));
+            }
+            for (next, count) in nexts {
+                let mut fields = state.iter().map(field).collect::<Vec<_>>();
+                fields.push(field(next));
+                fields.push(count.to_string());
+                lines.push(fields.join("\t"));
+            }
+        }
+        lines.sort();
+        for line in lines {
+            writeln!(w, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Draws an ASCII bar chart of the `n` most frequently seen transitions of the chain, one per
+    /// line from the most frequent down, for a quick look at a small chain in a terminal. Each
+    /// line reads like `the -> cat ####### 7`, with states of several tokens separated by spaces,
+    /// the beginning and end of a sentence shown as `<start>` and `<end>`, and bars scaled so
+    /// that the longest is forty characters. Transitions seen equally often are sorted by label.
+    pub fn print_histogram(&self, n: usize) -> String {
+        let show = |token: &Token<T>| match *token {
+            Some(ref token) => token.to_string(),
+            None => "<start>".to_owned(),
+        };
+        let mut transitions = Vec::new();
+        for (state, nexts) in &self.map {
+            let from = state.iter().map(show).collect::<Vec<_>>().join(" ");
+            for (next, &count) in nexts.iter().filter(|&(_, &count)| count > 0.0) {
+                let to = match *next {
+                    Some(ref next) => next.to_string(),
+                    None => "<end>".to_owned(),
+                };
+                transitions.push((format!("{} -> {}", from, to), count));
+            }
+        }
+        transitions.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        transitions.truncate(n);
+        let width = transitions
+            .iter()
+            .map(|t| t.0.chars().count())
+            .max()
+            .unwrap_or(0);
+        let max = transitions.first().map_or(0.0, |t| t.1);
+        let mut histogram = String::new();
+        for (label, count) in transitions {
+            let bar = "#".repeat(((count / max * 40.0).round() as usize).max(1));
+            histogram.push_str(&format!(
+                "{:<width$} {} {}\n",
+                label,
+                bar,
+                count,
+                width = width
+            ));
+        }
+        histogram
+    }
+}
+
+impl<T> Chain<T>
+where
+    T: Chainable + FromStr,
+{
+    /// Reads a chain from tab-separated values as written by `save_tsv`, with the order of the
+    /// chain given by its `\order` line, or else by the number of columns of the first transition.
+    /// Blank lines are skipped, and a transition that appears on several lines has its counts
+    /// added up, so the output of several chains of the same order can simply be concatenated. If
+    /// a line cannot be parsed, has a different number of columns than the order calls for, has a
+    /// count that is negative or not finite, or has a state that continues past the end of a
+    /// sentence, this returns an `InvalidData` error naming the offending line. Reading no lines
+    /// at all gives an empty chain of order one.
+    pub fn load_tsv<R: Read>(reader: R) -> Result<Chain<T>> {
+        let mut chain: Option<Chain<T>> = None;
+        for (number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let malformed = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("malformed transition on line {}", number + 1),
+                )
+            };
+            let mut fields = line.split('\t').collect::<Vec<_>>();
+            if fields[0] == "\\order" {
+                let order = match fields[..] {
+                    [_, order] => order.parse::<usize>().ok().filter(|&order| order != 0),
+                    _ => None,
+                }
+                .ok_or_else(malformed)?;
+                if chain.get_or_insert_with(|| Chain::of_order(order)).order != order {
+                    return Err(malformed());
+                }
+                continue;
+            }
+            let count = match chain {
+                Some(ref chain) if fields.len() == chain.order => None,
+                _ => Some(
+                    fields
+                        .pop()
+                        .and_then(|count| count.parse::<f64>().ok())
+                        .filter(|&count| count.is_finite() && count >= 0.0)
+                        .ok_or_else(malformed)?,
+                ),
+            };
+            if count.is_some() && fields.len() < 2 {
+                return Err(malformed());
+            }
+            let chain = chain.get_or_insert_with(|| Chain::of_order(fields.len() - 1));
+            let tokens = fields
+                .into_iter()
+                .map(|field| match unescape_tsv(field) {
+                    Some(Some(token)) => token.parse().map(Some).map_err(|_| malformed()),
+                    Some(None) => Ok(None),
+                    None => Err(malformed()),
+                })
+                .collect::<Result<Vec<Token<T>>>>()?;
+            if tokens.len() < chain.order || continues_past_end(&tokens[..chain.order]) {
+                return Err(malformed());
+            }
+            match count {
+                Some(count) if tokens.len() == chain.order + 1 => {
+                    chain.reinforce(&tokens[..chain.order], &tokens[chain.order], count)
+                }
+                None => {
+                    chain.map.entry(tokens).or_default();
+                }
+                Some(_) => return Err(malformed()),
+            }
+        }
+        Ok(chain.unwrap_or_default())
+    }
+
+    /// Feeds a file of comma-separated values into the chain. Each row of the file is parsed as a
+    /// sequence of tokens, with surrounding whitespace trimmed from every value, and fed into the
+    /// chain as its own sequence. Blank rows are skipped. If any value in a row cannot be parsed,
+    /// this stops and returns an `InvalidData` error naming the offending row. The rows before it
+    /// will already have been fed into the chain, but none of the malformed row will have been.
+    pub fn feed_csv_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Chain<T>> {
+        let reader = BufReader::new(File::open(path)?);
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let tokens = line
+                .split(',')
+                .map(|value| value.trim().parse())
+                .collect::<::std::result::Result<Vec<T>, _>>()
+                .map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("malformed value on row {}", number + 1),
+                    )
+                })?;
+            self.feed(tokens);
+        }
+        Ok(self)
+    }
+}
+
+/// Feeds each collection of tokens into the chain as its own sequence, exactly as if `feed` were
+/// called on each of them in turn.
+impl<T> Extend<Vec<T>> for Chain<T>
+where
+    T: Chainable,
+{
+    fn extend<I: IntoIterator<Item = Vec<T>>>(&mut self, iter: I) {
+        for tokens in iter {
+            self.feed(tokens);
+        }
+    }
+}
+
+/// Feeds a flat stream of tokens into the chain as one single sequence, exactly as if the tokens
+/// were collected and passed to `feed` all at once.
+impl<T> Extend<T> for Chain<T>
+where
+    T: Chainable,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.feed(iter.into_iter().collect::<Vec<_>>());
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl<T> Chain<T>
+where
+    T: Chainable + Serialize,
+{
+    /// Saves the current chain to the specified path.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = File::create(&path)?;
+        let data = yaml::to_string(self).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        file.write_all(data.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl<T> Chain<T>
+where
+    T: Chainable + DeserializeOwned,
+{
+    /// Loads a chain from the specified path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Chain<T>> {
+        let mut file = File::open(&path)?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+
+        let chain: Chain<T> =
+            yaml::from_str(&data).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        if chain.order == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "a chain must be of order one or more",
+            ));
+        }
+        Ok(chain)
+    }
+}
+
+/// How `feed_file_dedup` decides that two lines are duplicates of each other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DedupMode {
+    /// Lines are duplicates only if they are exactly identical.
+    Exact,
+    /// Lines are duplicates if they contain the same words, ignoring case, punctuation, and
+    /// spacing. Only alphanumeric characters are compared.
+    Normalized,
+}
+
+impl Chain<String> {
+    /// Makes the chain case-insensitive. From then on, every word fed into the chain as text is
+    /// folded to lowercase, so that differently cased spellings of a word share a single state.
+    /// The chain still counts how often each original spelling was seen, and generated text uses
+    /// the most common one: a chain that saw "Apple" ten times and "apple" twice emits "Apple".
+    /// The string-based methods, such as `generate_str_from_token`, fold their input in the same
+    /// way, but the generic token-based ones do not. This should be enabled before anything is fed
+    /// into the chain, as words fed in beforehand are never folded.
+    pub fn ignore_case(&mut self) -> &mut Chain<String> {
+        if self.surface_forms.is_none() {
+            self.surface_forms = Some(HashMap::new());
+        }
+        self
+    }
+
+    /// Determines whether or not the chain is case-insensitive.
+    pub fn is_case_insensitive(&self) -> bool {
+        self.surface_forms.is_some()
+    }
+
+    /// Normalizes a word the way the chain stores it.
+    fn normalize(&self, word: &str) -> String {
+        if self.is_case_insensitive() {
+            word.to_lowercase()
+        } else {
+            word.to_owned()
+        }
+    }
+
+    /// Feeds a sequence of words into the chain, recording their surface forms if the chain is
+    /// case-insensitive.
+    fn feed_words<'a, I: Iterator<Item = &'a str>>(&mut self, words: I) -> &mut Chain<String> {
+        let words = words.collect::<Vec<_>>();
+        if let Some(ref mut forms) = self.surface_forms {
+            for &word in &words {
+                *forms
+                    .entry(word.to_lowercase())
+                    .or_default()
+                    .entry(word.to_owned())
+                    .or_insert(0) += 1;
+            }
+        }
+        let tokens = words
+            .iter()
+            .map(|word| self.normalize(word))
+            .collect::<Vec<_>>();
+        self.feed(tokens)
+    }
+
+    /// Feeds a string of text into the chain.
+    pub fn feed_str(&mut self, string: &str) -> &mut Chain<String> {
+        self.feed_words(string.split(' '))
+    }
+
+    /// Feeds a string of text into the chain, split into words in a way that suits English text
+    /// better than `feed_str`. The text is split as follows:
+    ///
+    /// * Whitespace separates tokens and is otherwise dropped.
+    /// * A word is a run of alphanumeric characters, which carries on across every apostrophe,
+    ///   either `'` or `’`, and every hyphen that has an alphanumeric character on both sides. So
+    ///   contractions like `it's` and `don't` and compounds like `well-known` are single tokens.
+    /// * Every other character is a token of its own, so `cats, dogs!` is split into `cats`, `,`,
+    ///   `dogs`, and `!`. This includes apostrophes and hyphens at either end of a word, as in
+    ///   `'tis` or `dogs'`, and each hyphen of a dash like `--`.
+    ///
+    /// The whole string is fed as a single sentence, like with `feed_str`.
+    pub fn feed_str_smart(&mut self, text: &str) -> &mut Chain<String> {
+        self.feed_words(smart_words(text).into_iter())
+    }
+
+    /// Builds a chain out of a blob of text, for quick experiments. The text is split into
+    /// sentences at every `.`, `!`, and `?`, and each sentence is split into words at whitespace
+    /// and fed into the chain. The punctuation that ends the sentences is dropped, and so are
+    /// sentences without any words, such as those in between runs of punctuation like `?!`. Note
+    /// that this splits at every full stop, including those of abbreviations and numbers.
+    pub fn from_text(text: &str) -> Chain<String> {
+        let mut chain = Chain::new();
+        for sentence in text.split(['.', '!', '?']) {
+            chain.feed_words(sentence.split_whitespace());
+        }
+        chain
+    }
+
+    /// Feeds a properly formatted file into the chain. This file should be formatted such that
+    /// each line is a new sentence. Punctuation may be included if it is desired.
+    pub fn feed_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Chain<String>> {
+        let reader = BufReader::new(File::open(path)?);
+        for line in reader.lines() {
+            let line = line?;
+            self.feed_words(line.split_whitespace().filter(|word| !word.is_empty()));
+        }
+        Ok(self)
+    }
+
+    /// Feeds every file in the given directory into the chain with `feed_file`, in order of their
+    /// names. Subdirectories are not searched. If an `extension` such as `"txt"` is given, only
+    /// files with that extension are fed. A file that fails to be fed does not stop the others
+    /// from being fed: every failure is gathered, and if there were any, they are all reported
+    /// together in a single error, of the kind of the first one, once every file has been tried.
+    /// Lines read from a file before it failed stay fed. Failing to read the directory itself is
+    /// reported right away.
+    pub fn feed_dir<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        extension: Option<&str>,
+    ) -> Result<&mut Chain<String>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let matches = extension
+                .is_none_or(|extension| path.extension().is_some_and(|ext| ext == extension));
+            if path.is_file() && matches {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let mut failures = Vec::new();
+        for path in paths {
+            if let Err(e) = self.feed_file(&path) {
+                failures.push((path, e));
+            }
+        }
+        match failures.first() {
+            None => Ok(self),
+            Some((_, first)) => {
+                let kind = first.kind();
+                let message = failures
+                    .iter()
+                    .map(|(path, e)| format!("{}: {}", path.display(), e))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                Err(Error::new(kind, message))
+            }
+        }
+    }
+
+    /// Feeds a properly formatted file into the chain like `feed_file`, but skips any line that
+    /// duplicates one fed earlier in the same call, so that repeated lines do not inflate the
+    /// counts of their transitions. What counts as a duplicate is determined by `mode`. To find
+    /// duplicates, a 64-bit hash of every distinct line is kept until the whole file has been fed,
+    /// so the extra memory used grows with the number of distinct lines rather than their length.
+    /// Since only hashes are compared, a line may very rarely be skipped because its hash collides
+    /// with that of a different line.
+    pub fn feed_file_dedup<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mode: DedupMode,
+    ) -> Result<&mut Chain<String>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut seen = HashSet::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut hasher = DefaultHasher::new();
+            match mode {
+                DedupMode::Exact => line.hash(&mut hasher),
+                DedupMode::Normalized => line
+                    .split_whitespace()
+                    .map(|word| {
+                        word.chars()
+                            .filter(|c| c.is_alphanumeric())
+                            .flat_map(char::to_lowercase)
+                            .collect::<String>()
+                    })
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+                    .hash(&mut hasher),
+            }
+            if seen.insert(hasher.finish()) {
+                self.feed_words(line.split_whitespace().filter(|word| !word.is_empty()));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Converts the output of `generate(...)` on a String chain to a single String.
+    fn vec_to_string(vec: Vec<String>) -> String {
+        let mut ret = String::new();
+        for s in &vec {
+            ret.push_str(s);
+            ret.push(' ');
+        }
+        let len = ret.len();
+        if len > 0 {
+            ret.truncate(len - 1);
+        }
+        ret
+    }
+
+    /// Generates a random string of text.
+    pub fn generate_str(&self) -> String {
+        Chain::vec_to_string(self.generate())
+    }
+
+    /// Generates a random string of text that ends with `terminator`, such as `"."`. If the last
+    /// generated word already ends with terminal punctuation (a `.`, `!`, or `?`), as is common in
+    /// chains trained with punctuation attached to words, nothing is appended. This avoids
+    /// artifacts like `"cats?."`.
+    pub fn generate_str_terminated(&self, terminator: &str) -> String {
+        let mut string = self.generate_str();
+        if !string.is_empty() && !string.ends_with(['.', '!', '?']) {
+            string.push_str(terminator);
+        }
+        string
+    }
+
+    /// Generates a random string of text in presentable casing. In a case-insensitive chain, every
+    /// word is already rendered in the form it was most often seen in during training, which
+    /// keeps acronyms and names as they were written; this additionally capitalizes the first
+    /// letter of the output, as at the start of a sentence. In a case-sensitive chain, only the
+    /// capitalization applies.
+    pub fn generate_str_cased(&self) -> String {
+        let mut string = self.generate_str();
+        if let Some(first) = string.chars().next() {
+            let upper = first.to_uppercase().collect::<String>();
+            string.replace_range(..first.len_utf8(), &upper);
+        }
+        string
+    }
+
+    /// Generates a paragraph of `sentences` random sentences joined by `sentence_sep`, such as
+    /// `" "` or `"\n"`. Each sentence is generated independently, from the start of a sentence
+    /// through to its end, just like `generate_str`. Empty sentences are left out, so an empty
+    /// chain generates an empty paragraph.
+    pub fn generate_paragraph(&self, sentences: usize, sentence_sep: &str) -> String {
+        (0..sentences)
+            .map(|_| self.generate_str())
+            .filter(|sentence| !sentence.is_empty())
+            .collect::<Vec<_>>()
+            .join(sentence_sep)
+    }
+
+    /// Generates a random string of text starting with the desired token. This returns an empty
+    /// string if the token is not found.
+    pub fn generate_str_from_token(&self, string: &str) -> String {
+        Chain::vec_to_string(self.generate_from_token(self.normalize(string)))
+    }
+
+    /// Generates a random string of text starting with the desired tokens. This returns an empty
+    /// string if the tokens are not found.
+    pub fn generate_str_from_tokens(&self, string: &str) -> String {
+        Chain::vec_to_string(
+            self.generate_from_tokens(
+                string
+                    .split_whitespace()
+                    .map(|s| self.normalize(s))
+                    .collect::<Vec<String>>(),
+            ),
+        )
+    }
+
+    /// Produces an infinite iterator of generated strings.
+    pub fn str_iter(&self) -> InfiniteChainStringIterator<'_> {
+        let vec_to_string: fn(Vec<String>) -> String = Chain::vec_to_string;
+        self.iter().map(vec_to_string)
+    }
+
+    /// Produces a sized iterator of generated strings.
+    pub fn str_iter_for(&self, size: usize) -> SizedChainStringIterator<'_> {
+        let vec_to_string: fn(Vec<String>) -> String = Chain::vec_to_string;
+        self.iter_for(size).map(vec_to_string)
+    }
+
+    /// Produces an iterator over the words of a single generated sentence, which generates each
+    /// word only once it is asked for and ends with the sentence. This suits streaming the output
+    /// somewhere a word at a time, and bounding it with `take`, without collecting the sentence
+    /// first.
+    pub fn words(&self) -> WordIterator<'_> {
+        WordIterator {
+            chain: self,
+            state: self.begin_generation(),
+        }
+    }
+}
+
+/// A sized iterator over a Markov chain of strings.
+pub type SizedChainStringIterator<'a> =
+    Map<SizedChainIterator<'a, String>, fn(Vec<String>) -> String>;
+
+/// A sized iterator over a Markov chain.
+pub struct SizedChainIterator<'a, T: Chainable + 'a> {
+    chain: &'a Chain<T>,
+    size: usize,
+}
+
+impl<'a, T> Iterator for SizedChainIterator<'a, T>
+where
+    T: Chainable + 'a,
+{
+    type Item = Vec<T>;
+    fn next(&mut self) -> Option<Vec<T>> {
+        if self.size > 0 {
+            self.size -= 1;
+            Some(self.chain.generate())
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.size, Some(self.size))
+    }
+}
+
+/// An infinite iterator over a Markov chain of strings.
+pub type InfiniteChainStringIterator<'a> =
+    Map<InfiniteChainIterator<'a, String>, fn(Vec<String>) -> String>;
+
+/// An infinite iterator over a Markov chain.
+pub struct InfiniteChainIterator<'a, T: Chainable + 'a> {
+    chain: &'a Chain<T>,
+}
+
+impl<'a, T> Iterator for InfiniteChainIterator<'a, T>
+where
+    T: Chainable + 'a,
+{
+    type Item = Vec<T>;
+    fn next(&mut self) -> Option<Vec<T>> {
+        Some(self.chain.generate())
+    }
+}
+
+/// An iterator over the words of a sentence generated from a Markov chain of strings, as
+/// produced by `Chain::words`.
+pub struct WordIterator<'a> {
+    chain: &'a Chain<String>,
+    state: GenerationState<String>,
+}
+
+impl<'a> Iterator for WordIterator<'a> {
+    type Item = String;
+    fn next(&mut self) -> Option<String> {
+        self.chain.advance(&mut self.state, &mut self.chain.rng())
+    }
+}
+
+/// A never-ending random walk over a Markov chain.
+pub struct RandomWalkIterator<'a, T: Chainable + 'a> {
+    chain: &'a Chain<T>,
+    curs: Vec<Token<T>>,
+    /// The number of steps taken since the current sentence began.
+    steps: usize,
+    /// The step at which to check next whether the walk is caught in an absorbing state.
+    check_at: usize,
+}
+
+impl<'a, T> RandomWalkIterator<'a, T>
+where
+    T: Chainable + 'a,
+{
+    /// Groups the walk into sentences, yielding the tokens from each start of a sentence to its
+    /// end as a single collection.
+    pub fn sentences(self) -> RandomWalkSentences<'a, T> {
+        RandomWalkSentences { walk: self }
+    }
+
+    /// Takes a single step of the walk. This returns `Some(None)` at the end of each sentence, and
+    /// `None` only if the walk cannot continue at all because no sentence can begin with a token.
+    fn advance(&mut self) -> Option<Token<T>> {
+        self.steps += 1;
+        let mut trapped = false;
+        if self.steps == self.check_at {
+            self.check_at *= 2;
+            trapped = self.chain.absorbing_states().contains(&self.curs);
+        }
+        let next = self
+            .chain
+            .map
+            .get(&self.curs)
+            .filter(|_| !trapped)
+            .and_then(|nexts| {
+                weighted_choice(
+                    nexts.iter().map(|(next, &count)| (next, count)),
+                    &mut self.chain.rng(),
+                )
+            });
+        match next {
+            Some(Some(token)) => {
+                self.curs.remove(0);
+                self.curs.push(Some(token.clone()));
+                Some(Some(self.chain.surface_form(token)))
+            }
+            _ => {
+                let start = vec![None; self.chain.order];
+                if self.curs == start
+                    && !self
+                        .chain
+                        .possible_successors(&start)
+                        .iter()
+                        .any(Option::is_some)
+                {
+                    return None;
+                }
+                self.curs = start;
+                self.steps = 0;
+                self.check_at = self.chain.map.len().max(1);
+                Some(None)
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for RandomWalkIterator<'a, T>
+where
+    T: Chainable + 'a,
+{
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(token) = self.advance()? {
+                return Some(token);
+            }
+        }
+    }
+}
+
+/// What feeding a collection of tokens into a chain would change, as worked out by
+/// `Chain::feed_preview`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FeedStats {
+    /// The number of states that the chain has never seen.
+    pub new_states: usize,
+    /// The number of transitions that the chain has never seen.
+    pub new_transitions: usize,
+}
+
+/// The progress of a generation carried out one token at a time, as begun by
+/// `Chain::begin_generation`. It can be serialized to stash it away between steps.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GenerationState<T>
+where
+    T: Chainable,
+{
+    state: Vec<Token<T>>,
+    finished: bool,
+}
+
+impl<T> GenerationState<T>
+where
+    T: Chainable,
+{
+    /// Determines whether or not the generation has reached the end of a sentence.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// A strategy for picking the next token during generation, as used by
+/// `Chain::generate_with_sampler`. Samplers can wrap one another to combine strategies.
+pub trait Sampler<T> {
+    /// Picks one of the given successors, which map each candidate token to the number of times it
+    /// followed the current state, with `None` standing for the end of a sentence. Candidates with
+    /// a count that is not positive should never be picked. Returning `None` stops generation.
+    fn pick(
+        &self,
+        successors: &HashMap<Option<T>, f64>,
+        rng: &mut dyn RngCore,
+    ) -> Option<Option<T>>;
+}
+
+/// Picks each successor in proportion to its count, just like `Chain::generate`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Proportional;
+
+impl<T: Clone> Sampler<T> for Proportional {
+    fn pick(
+        &self,
+        successors: &HashMap<Option<T>, f64>,
+        mut rng: &mut dyn RngCore,
+    ) -> Option<Option<T>> {
+        weighted_choice(
+            successors.iter().map(|(next, &count)| (next, count)),
+            &mut rng,
+        )
+    }
+}
+
+/// Always picks the most frequently seen successor, breaking ties arbitrarily.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Greedy;
+
+impl<T: Clone> Sampler<T> for Greedy {
+    fn pick(&self, successors: &HashMap<Option<T>, f64>, _: &mut dyn RngCore) -> Option<Option<T>> {
+        sorted_by_count(successors)
+            .first()
+            .filter(|&&(_, count)| count > 0.0)
+            .map(|&(next, _)| next.clone())
+    }
+}
+
+/// Picks among only the given number of most frequently seen successors, in proportion to their
+/// counts, as in `Chain::generate_top_k`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TopK(pub usize);
+
+impl<T: Clone> Sampler<T> for TopK {
+    fn pick(
+        &self,
+        successors: &HashMap<Option<T>, f64>,
+        mut rng: &mut dyn RngCore,
+    ) -> Option<Option<T>> {
+        weighted_choice(
+            sorted_by_count(successors).into_iter().take(self.0),
+            &mut rng,
+        )
+    }
+}
+
+/// Picks among only the smallest set of most likely successors whose probabilities add up to at
+/// least the given one, in proportion to their counts, as in `Chain::generate_top_p`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TopP(pub f64);
+
+impl<T: Clone> Sampler<T> for TopP {
+    fn pick(
+        &self,
+        successors: &HashMap<Option<T>, f64>,
+        mut rng: &mut dyn RngCore,
+    ) -> Option<Option<T>> {
+        let total = successors.values().sum::<f64>();
+        let mut cumulative = 0.0;
+        let mut nucleus = Vec::new();
+        for (next, count) in sorted_by_count(successors) {
+            if !nucleus.is_empty() && cumulative >= self.0 {
+                break;
+            }
+            cumulative += count / total;
+            nucleus.push((next, count));
+        }
+        weighted_choice(nucleus, &mut rng)
+    }
+}
+
+/// A read-only Markov chain that only keeps the probabilities of its transitions, as exported by
+/// `Chain::to_compact` for deployment. The successors of each state are stored contiguously
+/// alongside their cumulative probabilities, so that sampling the next token is a binary search.
+/// Deserializing a compact chain fails if its arrays do not fit together.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "CompactParts<T>")]
+pub struct CompactChain<T>
+where
+    T: Chainable,
+{
+    /// The range of successors of state `i` is `offsets[i]..offsets[i + 1]`, with the beginning of
+    /// a sentence as state `0`.
+    offsets: Vec<usize>,
+    tokens: Vec<Token<T>>,
+    /// The state reached through each successor, or `None` if generation stops there.
+    targets: Vec<Option<usize>>,
+    probabilities: Vec<f32>,
+    #[serde(skip)]
+    rng: ChainRng,
+}
+
+/// The arrays of a `CompactChain` as they are deserialized, before they are checked to fit
+/// together.
+#[derive(Deserialize)]
+struct CompactParts<T> {
+    offsets: Vec<usize>,
+    tokens: Vec<Token<T>>,
+    targets: Vec<Option<usize>>,
+    probabilities: Vec<f32>,
+}
+
+impl<T> TryFrom<CompactParts<T>> for CompactChain<T>
+where
+    T: Chainable,
+{
+    type Error = String;
+
+    fn try_from(parts: CompactParts<T>) -> ::std::result::Result<CompactChain<T>, String> {
+        let states = parts.offsets.len().saturating_sub(1);
+        if states == 0 || parts.offsets[0] != 0 {
+            return Err("the offsets must begin with zero and cover at least one state".into());
+        }
+        if parts.offsets.windows(2).any(|pair| pair[0] > pair[1])
+            || parts.offsets[states] != parts.tokens.len()
+        {
+            return Err("the offsets must rise up to the number of successors".into());
+        }
+        if parts.targets.len() != parts.tokens.len()
+            || parts.probabilities.len() != parts.tokens.len()
+        {
+            return Err("every successor must have a target and a probability".into());
+        }
+        if parts
+            .targets
+            .iter()
+            .flatten()
+            .any(|&target| target >= states)
+        {
+            return Err("a successor leads to a state that does not exist".into());
+        }
+        Ok(CompactChain {
+            offsets: parts.offsets,
+            tokens: parts.tokens,
+            targets: parts.targets,
+            probabilities: parts.probabilities,
+            rng: ChainRng::default(),
+        })
+    }
+}
+
+impl<T> CompactChain<T>
+where
+    T: Chainable,
+{
+    /// Generates a collection of tokens from the chain, with the random number generator of the
+    /// chain it was exported from, if that was seeded.
+    pub fn generate(&self) -> Vec<T> {
+        let mut rng = self.rng.handle();
+        let mut ret = Vec::new();
+        let mut state = 0;
+        loop {
+            let (lo, hi) = (self.offsets[state], self.offsets[state + 1]);
+            if lo == hi {
+                break;
+            }
+            let p = rng.gen::<f32>();
+            let i = lo
+                + self.probabilities[lo..hi]
+                    .partition_point(|&cumulative| cumulative <= p)
+                    .min(hi - lo - 1);
+            match self.tokens[i] {
+                Some(ref token) => ret.push(token.clone()),
+                None => break,
+            }
+            match self.targets[i] {
+                Some(next) => state = next,
+                None => break,
+            }
+        }
+        ret
+    }
+}
+
+/// A two-level Markov chain for generating paragraphs whose sentences follow on from one another.
+/// It is made of two ordinary chains: a chain of sentences, fed every paragraph with each of its
+/// sentences as a single token, which learns how sentences flow from one to the next, and a chain
+/// of tokens, fed every sentence as usual, which learns how sentences are put together.
+///
+/// Generation first walks the chain of sentences to lay out the flow of a paragraph, then fills
+/// each slot in with a fresh sentence from the chain of tokens that begins like the sentence in
+/// that slot, so that the paragraph keeps its learned structure without repeating it verbatim.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ParagraphChain<T>
+where
+    T: Chainable,
+{
+    sentences: Chain<Vec<T>>,
+    words: Chain<T>,
+}
+
+impl<T> Default for ParagraphChain<T>
+where
+    T: Chainable,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ParagraphChain<T>
+where
+    T: Chainable,
+{
+    /// Constructs a new paragraph chain whose chain of tokens is of order one.
+    pub fn new() -> ParagraphChain<T> {
+        Self::of_order(1)
+    }
+
+    /// Constructs a new paragraph chain whose chain of tokens is of the specified order. The chain
+    /// of sentences is always of order one.
+    pub fn of_order(order: usize) -> ParagraphChain<T> {
+        ParagraphChain {
+            sentences: Chain::new(),
+            words: Chain::of_order(order),
+        }
+    }
+
+    /// Feeds a paragraph, given as its sentences in order, into both levels of the chain.
+    pub fn feed_paragraph(&mut self, sentences: Vec<Vec<T>>) -> &mut ParagraphChain<T> {
+        for sentence in &sentences {
+            self.words.feed(sentence);
+        }
+        self.sentences.feed(sentences);
+        self
+    }
+
+    /// Generates a paragraph from the chain, as its sentences in order. Each sentence begins with
+    /// the first `order` tokens of the learned sentence in its slot, and is continued from there
+    /// by the chain of tokens.
+    pub fn generate(&self) -> Vec<Vec<T>> {
+        let order = self.words.order();
+        self.sentences
+            .generate()
+            .into_iter()
+            .map(|slot| {
+                let opening = slot[..order.min(slot.len())].to_vec();
+                let sentence = self.words.generate_from_tokens(opening);
+                if sentence.is_empty() {
+                    slot
+                } else {
+                    sentence
+                }
+            })
+            .collect()
+    }
+
+    /// Gets the chain of sentences, which every paragraph is fed into as a single sequence.
+    pub fn sentence_chain(&self) -> &Chain<Vec<T>> {
+        &self.sentences
+    }
+
+    /// Gets the chain of tokens, which every sentence is fed into on its own.
+    pub fn word_chain(&self) -> &Chain<T> {
+        &self.words
+    }
+}
+
+/// A generator that favors the transitions it has used least, as produced by
+/// `Chain::exploring_generator`.
+pub struct ExploringGenerator<'a, T: Chainable + 'a> {
+    chain: &'a Chain<T>,
+    uses: HashMap<(Vec<Token<T>>, Token<T>), usize>,
+}
+
+impl<'a, T> ExploringGenerator<'a, T>
+where
+    T: Chainable + 'a,
+{
+    /// Generates a collection of tokens from the chain, picking each successor in proportion to
+    /// `count / (1 + uses)`, where `uses` is the number of times this generator has already taken
+    /// that transition. Every transition taken is counted, so this changes the state of the
+    /// generator, and with it the odds of every later generation.
+    pub fn generate(&mut self) -> Vec<T> {
+        let uses = &mut self.uses;
+        self.chain.walk(
+            vec![None; self.chain.order],
+            Vec::new(),
+            &mut self.chain.rng(),
+            |curs, nexts, _, rng| {
+                let weighted = nexts.iter().map(|(next, &count)| {
+                    let used = uses
+                        .get(&(curs.to_vec(), next.clone()))
+                        .cloned()
+                        .unwrap_or(0);
+                    (next, count / (1 + used) as f64)
+                });
+                let next = weighted_choice(weighted, rng)?;
+                *uses.entry((curs.to_vec(), next.clone())).or_insert(0) += 1;
+                Some(next)
+            },
+        )
+    }
+}
+
+/// A never-ending random walk over a Markov chain, grouped into sentences.
+pub struct RandomWalkSentences<'a, T: Chainable + 'a> {
+    walk: RandomWalkIterator<'a, T>,
+}
+
+impl<'a, T> Iterator for RandomWalkSentences<'a, T>
+where
+    T: Chainable + 'a,
+{
+    type Item = Vec<T>;
+    fn next(&mut self) -> Option<Vec<T>> {
+        let mut sentence = Vec::new();
+        while let Some(token) = self.walk.advance()? {
+            sentence.push(token);
+        }
+        Some(sentence)
+    }
+}
+
+/// A partial path through a chain, for searching it. Candidates are ordered so that the one with
+/// the lowest cost is the greatest, with ties broken in favor of the path whose tokens come first.
+/// A candidate without a state has reached the end of a sentence.
+struct Candidate<T> {
+    cost: f64,
+    path: Vec<T>,
+    state: Option<Vec<Token<T>>>,
+}
+
+impl<T: Ord> Ord for Candidate<T> {
+    fn cmp(&self, other: &Candidate<T>) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.path.cmp(&self.path))
+            .then_with(|| other.state.cmp(&self.state))
+    }
+}
+
+impl<T: Ord> PartialOrd for Candidate<T> {
+    fn partial_cmp(&self, other: &Candidate<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> PartialEq for Candidate<T> {
+    fn eq(&self, other: &Candidate<T>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Candidate<T> {}
+
+/// Checks whether a state has tokens after the end of a sentence, which no chain can reach.
+fn continues_past_end<T>(state: &[Token<T>]) -> bool {
+    state
+        .iter()
+        .skip_while(|token| token.is_none())
+        .any(Option::is_none)
+}
+
+/// Computes the state that follows `state` once `next` is emitted.
+fn next_state<T: Clone>(state: &[Token<T>], next: &Token<T>) -> Vec<Token<T>> {
+    let mut next_state = state[1..].to_vec();
+    next_state.push(next.clone());
+    next_state
+}
+
+/// Splits a string of text into words and punctuation, as fed by `Chain::feed_str_smart`.
+fn smart_words(text: &str) -> Vec<&str> {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let offset = |i: usize| chars.get(i).map_or(text.len(), |&(offset, _)| offset);
+    let is_word = |i: usize| chars.get(i).is_some_and(|&(_, c)| c.is_alphanumeric());
+    let is_joiner = |i: usize| {
+        chars
+            .get(i)
+            .is_some_and(|&(_, c)| matches!(c, '\'' | '’' | '-'))
+    };
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        if is_word(i) {
+            i += 1;
+            while is_word(i) || (is_joiner(i) && is_word(i + 1)) {
+                i += 1;
+            }
+        } else {
+            i += 1;
+            if chars[start].1.is_whitespace() {
+                continue;
+            }
+        }
+        words.push(&text[offset(start)..offset(i)]);
+    }
+    words
+}
+
+/// Escapes a token for a field of tab-separated values, as written by `Chain::save_tsv`.
+fn escape_tsv(token: &str) -> String {
+    let mut escaped = String::with_capacity(token.len());
+    for c in token.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverses `escape_tsv`, giving `Some(None)` for a sentence boundary, or `None` if the field is
+/// not validly escaped.
+fn unescape_tsv(field: &str) -> Option<Token<String>> {
+    if field == "\\N" {
+        return Some(None);
+    }
+    let mut token = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            token.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => token.push('\\'),
+            't' => token.push('\t'),
+            'n' => token.push('\n'),
+            'r' => token.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(Some(token))
+}
+
+/// Computes the Levenshtein distance between two sequences: the fewest insertions, deletions, and
+/// substitutions of single elements needed to turn one into the other.
+fn edit_distance<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + if x == y { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Lists the successors of a state from the most to the least frequently seen.
+fn sorted_by_count<T>(nexts: &HashMap<Token<T>, f64>) -> Vec<(&Token<T>, f64)> {
+    let mut sorted = nexts
+        .iter()
+        .map(|(next, &count)| (next, count))
+        .collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    sorted
+}
+
+/// Picks one of the given items at random, with probability proportional to its weight. Items with
+/// a weight that is not positive are never picked, and this returns `None` if there are no others.
+fn weighted_choice<'a, K, I, R>(items: I, rng: &mut R) -> Option<K>
+where
+    K: Clone + 'a,
+    I: IntoIterator<Item = (&'a K, f64)>,
+    R: Rng,
+{
+    let items = items
+        .into_iter()
+        .filter(|&(_, weight)| weight > 0.0)
+        .collect::<Vec<_>>();
+    let sum = items.iter().map(|&(_, weight)| weight).sum::<f64>();
+    let cap = rng.gen::<f64>() * sum;
+    let mut acc = 0.0;
+    for &(item, weight) in &items {
+        acc += weight;
+        if acc > cap {
+            return Some(item.clone());
+        }
+    }
+    items.last().map(|&(item, _)| item.clone())
+}
+
+/// A collection of states for the Markov chain.
+trait States<T: PartialEq> {
+    /// Adds a state to this states collection.
+    fn add(&mut self, token: Token<T>, count: f64);
+    /// Gets the next state from this collection of states, picked in proportion to its count, or
+    /// the end of a sentence if there are none.
+    fn next<R: Rng>(&self, rng: &mut R) -> Token<T>;
+    /// Computes the Shannon entropy, in bits, of the distribution of the next state.
+    fn entropy(&self) -> f64;
+}
+
+impl<T> States<T> for HashMap<Token<T>, f64>
+where
+    T: Chainable,
+{
+    fn add(&mut self, token: Token<T>, count: f64) {
+        match self.entry(token) {
+            Occupied(mut e) => *e.get_mut() += count,
+            Vacant(e) => {
+                e.insert(count);
+            }
+        }
+    }
+
+    fn next<R: Rng>(&self, rng: &mut R) -> Token<T> {
+        weighted_choice(self.iter().map(|(key, &value)| (key, value)), rng).unwrap_or(None)
+    }
+
+    fn entropy(&self) -> f64 {
+        let total = self.values().filter(|&&count| count > 0.0).sum::<f64>();
+        self.values()
+            .filter(|&&count| count > 0.0)
+            .map(|&count| {
+                let p = count / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Chain, DeadEndPolicy, DedupMode, FeedStats, Greedy, ParagraphChain, Proportional, Sampler,
+        TopK, UnknownTokenPolicy,
+    };
+    use rand::rngs::{SmallRng, StdRng};
+    use rand::RngCore;
+    use rand::{thread_rng, Rng, SeedableRng};
+    use std::collections::{HashMap, HashSet};
+    use std::io::ErrorKind;
+    use std::sync::mpsc;
+    use std::{env, fs, thread};
+
+    #[test]
+    fn new() {
+        Chain::<u8>::new();
+        Chain::<String>::new();
+    }
+
+    #[test]
+    fn order() {
+        assert_eq!(Chain::<u8>::new().order(), 1);
+        assert_eq!(Chain::<u8>::of_order(3).order(), 3);
+    }
+
+    #[test]
+    fn is_empty() {
+        let mut chain = Chain::new();
+        assert!(chain.is_empty());
+        chain.feed(vec![1u8, 2, 3]);
+        assert!(!chain.is_empty());
+    }
+
+    #[test]
+    fn contains() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]);
+        assert!(chain.contains(&10));
+        assert!(!chain.contains(&12));
+    }
+
+    #[test]
+    fn vocabulary_coverage() {
+        let mut chain = Chain::new();
+        chain.feed(vec!["a", "b"]).feed(vec!["c"]);
+        assert_eq!(chain.vocabulary_coverage(&["a", "c", "d", "e"]), 0.5);
+        assert_eq!(chain.vocabulary_coverage(&["a"]), 1.0);
+        assert_eq!(chain.vocabulary_coverage(&[]), 1.0);
+    }
+
+    #[test]
+    fn surprisal() {
+        let mut chain = Chain::new();
+        chain
+            .feed([1u8, 2])
+            .feed([1u8, 3])
+            .feed([1u8, 3])
+            .feed([1u8, 3]);
+        assert_eq!(chain.surprisal(&1, &2), 2.0);
+        assert!((chain.surprisal(&1, &3) - (4.0f64 / 3.0).log2()).abs() < 1e-12);
+        assert_eq!(chain.surprisal(&2, &1), f64::INFINITY);
+        assert_eq!(chain.surprisal(&9, &1), f64::INFINITY);
+    }
+
+    #[test]
+    fn has_transition() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        assert!(chain.has_transition(&5, &12));
+        assert!(!chain.has_transition(&3, &12));
+        assert!(!chain.has_transition(&7, &3));
+
+        let mut chain = Chain::of_order(2);
+        chain.feed(vec![3u8, 5, 10]).feed(vec![2, 5, 12]);
+        assert!(chain.has_transition(&5, &10));
+        assert!(chain.has_transition(&5, &12));
+        assert!(!chain.has_transition(&3, &10));
+    }
+
+    #[test]
+    fn validate() {
+        let mut chain = Chain::of_order(2);
+        chain.feed([1u8, 2, 3]).feed([2, 2]);
+        assert_eq!(chain.validate(), Ok(()));
+        chain
+            .map
+            .get_mut(&vec![None, None])
+            .unwrap()
+            .insert(Some(4), 1.0);
+        assert!(chain.validate().is_err());
+        chain.map.insert(vec![Some(4)], HashMap::new());
+        assert!(chain.validate().is_err());
+        chain.map.remove(&vec![Some(4)]);
+        chain.map.insert(
+            vec![None, Some(4)],
+            vec![(None, -1.0)].into_iter().collect(),
+        );
+        assert!(chain.validate().is_err());
+        chain
+            .map
+            .insert(vec![None, Some(4)], vec![(None, 1.0)].into_iter().collect());
+        assert_eq!(chain.validate(), Ok(()));
+        chain.map.insert(vec![Some(3), None], HashMap::new());
+        assert!(chain.validate().is_err());
+        chain.map.clear();
+        assert!(chain.validate().is_err());
+    }
+
+    #[test]
+    fn feed() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3, 5, 10]).feed(vec![5, 12]);
+    }
+
+    #[test]
+    fn feed_debug() {
+        let mut chain = Chain::of_order(2);
+        assert!(chain.feed_debug(Vec::<u8>::new()).is_empty());
+        assert!(chain.is_empty());
+        assert_eq!(
+            chain.feed_debug(vec![1u8, 2]),
+            vec![None, None, Some(1), Some(2), None]
+        );
+        let mut expected = Chain::of_order(2);
+        expected.feed(vec![1u8, 2]);
+        assert_eq!(chain, expected);
+    }
+
+    #[test]
+    fn feed_preview() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2, 3]);
+        let before = chain.clone();
+        let stats = chain.feed_preview(&[1, 4, 1, 4]);
+        assert_eq!(chain, before);
+        assert_eq!(
+            stats,
+            FeedStats {
+                new_states: 1,
+                new_transitions: 3,
+            }
+        );
+        assert_eq!(chain.feed_preview(&[1, 2, 3]), FeedStats::default());
+        assert_eq!(chain.feed_preview(&[]), FeedStats::default());
+    }
+
+    #[test]
+    fn feed_sentences_iter() {
+        let mut chain = Chain::of_order(2);
+        chain.feed_sentences_iter((1u8..4).map(|n| (0..n).collect::<Vec<_>>()));
+        let mut expected = Chain::of_order(2);
+        expected.feed([0u8]).feed([0u8, 1]).feed([0u8, 1, 2]);
+        assert_eq!(chain, expected);
+    }
+
+    #[test]
+    fn feed_chunked() {
+        let mut chain = Chain::new();
+        chain.feed_chunked([1u8, 2, 3, 4, 5], 2);
+        let mut expected = Chain::new();
+        expected.feed([1u8, 2]).feed([3, 4]).feed([5]);
+        assert_eq!(chain, expected);
+    }
+
+    #[test]
+    fn feed_skipgram() {
+        let mut chain = Chain::new();
+        chain.feed_skipgram([1u8, 2, 3], 1);
+        assert_eq!(chain.count(&1, &3), 1);
+        assert_eq!(chain.count(&1, &2), 0);
+        assert_eq!(chain.generate(), vec![2]);
+        chain.feed_skipgram([1u8], 5);
+        assert_eq!(chain.count(&1, &3), 1);
+        let mut adjacent = Chain::of_order(2);
+        adjacent.feed_skipgram([1u8, 2, 3], 0);
+        assert_eq!(adjacent.map, Chain::of_order(2).feed([1u8, 2, 3]).map);
+    }
+
+    #[test]
+    fn feed_collapse_repeats() {
+        let mut chain = Chain::new();
+        chain.feed_collapse_repeats(vec!["very", "very", "good", "good"]);
+        let mut expected = Chain::new();
+        expected.feed(vec!["very", "good"]);
+        assert_eq!(chain, expected);
+    }
+
+    #[test]
+    fn feed_weighted() {
+        let mut chain = Chain::new();
+        chain.feed_weighted(vec![3u8, 5], 0.5).feed(vec![3, 5]);
+        assert_eq!(chain.weight(&3, &5), 1.5);
+        assert_eq!(chain.count(&3, &5), 2);
+        assert_eq!(chain.weight(&5, &3), 0.0);
+        assert_eq!(chain.count(&5, &3), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn feed_weighted_negative() {
+        Chain::new().feed_weighted(vec![3u8, 5], -1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn feed_weighted_not_finite() {
+        Chain::new().feed_weighted(vec![3u8, 5], f64::NAN);
+    }
+
+    #[test]
+    fn feed_with_order_weights() {
+        let mut chain = Chain::of_order(2);
+        assert_eq!(chain.lower_order_chain(1), None);
+        chain.feed_with_order_weights(vec![1u8, 2, 3], &[0.5, 2.0]);
+        let mut expected = Chain::of_order(2);
+        expected.feed_weighted(vec![1u8, 2, 3], 2.0);
+        assert_eq!(chain.lower_order_chain(2).unwrap().map, expected.map);
+        let first = chain.lower_order_chain(1).unwrap();
+        assert_eq!(first.order(), 1);
+        assert_eq!(first.weight(&1, &2), 0.5);
+        assert_eq!(first.weight(&2, &3), 0.5);
+        assert_eq!(chain.weight(&1, &2), 2.0);
+        assert_eq!(chain.lower_order_chain(0), None);
+        assert_eq!(chain.lower_order_chain(3), None);
+    }
+
+    #[test]
+    fn feed_tagged() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2, 3]);
+        let expected = chain.clone();
+        chain
+            .feed_tagged(7, vec![1u8, 2, 4])
+            .feed_tagged(7, vec![5u8])
+            .feed_tagged(8, vec![1u8, 2, 3]);
+        assert!(chain.contains(&4));
+        chain.remove_tagged(7).remove_tagged(7);
+        assert!(!chain.contains(&4));
+        assert!(!chain.contains(&5));
+        assert_eq!(chain.weight(&1, &2), 2.0);
+        chain.remove_tagged(8);
+        assert_eq!(chain.map, expected.map);
+        assert_eq!(chain.lengths, expected.lengths);
+
+        chain
+            .feed_tagged(9, vec![1, 2])
+            .feed_with_decay(vec![1, 2, 3], 0.5)
+            .remove_tagged(9);
+        let mut expected = expected.clone();
+        expected.feed_with_decay(vec![1, 2, 3], 0.5);
+        assert_eq!(chain.map, expected.map);
+        assert_eq!(chain.lengths, expected.lengths);
+        assert_eq!(chain.openings, expected.openings);
+
+        let mut chain = Chain::new();
+        chain.feed_tagged(7, vec!["USA", "wins"]);
+        chain.add_alias("US", "USA").remove_tagged(7);
+        assert_eq!(chain.map, Chain::new().map);
+    }
+
+    #[test]
+    fn add_alias() {
+        let mut chain = Chain::new();
+        chain.feed(vec!["USA", "wins"]);
+        chain.add_alias("US", "USA").add_alias("US", "America");
+        chain.add_alias("America", "United States");
+        chain
+            .feed(vec!["USA", "wins"])
+            .feed(vec!["United States", "wins"]);
+        assert_eq!(chain.weight(&"US", &"wins"), 2.0);
+        assert_eq!(chain.weight(&"USA", &"wins"), 1.0);
+        assert!(!chain.contains(&"United States"));
+        assert_eq!(
+            chain.feed_debug(vec!["America"]),
+            vec![None, Some("US"), None]
+        );
+    }
+
+    #[test]
+    fn feed_with_decay() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![3u8, 5])
+            .feed_with_decay(vec![3, 7], 0.5)
+            .feed_with_decay(vec![3, 7], 0.5);
+        let counts = &chain.map[&vec![Some(3)]];
+        assert_eq!(counts[&Some(5)], 0.25);
+        assert_eq!(counts[&Some(7)], 1.5);
+        assert_eq!(chain.map[&vec![None]][&Some(3)], 1.75);
+    }
+
+    #[test]
+    fn from_map() {
+        let mut map = HashMap::new();
+        map.insert(0u8, vec![(1, 2.0), (2, 1.0)].into_iter().collect());
+        map.insert(1, vec![(2, 2.0)].into_iter().collect());
+        map.insert(2, vec![(9, 3.0)].into_iter().collect());
+        let chain = Chain::from_map(map, 0, 9).unwrap();
+        let mut expected = Chain::new();
+        expected.feed(vec![1u8, 2]).feed(vec![1, 2]).feed(vec![2]);
+        assert_eq!(chain.map, expected.map);
+    }
+
+    #[test]
+    fn from_map_without_start() {
+        let mut map = HashMap::new();
+        map.insert(1u8, vec![(9, 1.0)].into_iter().collect());
+        assert!(Chain::from_map(map.clone(), 0, 9).is_err());
+        map.insert(0, vec![(1, 1.0)].into_iter().collect());
+        assert!(Chain::from_map(map.clone(), 0, 9).is_ok());
+        assert!(Chain::from_map(map, 0, 8).is_err());
+    }
+
+    #[test]
+    fn feed_transitions() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let mut new_chain = Chain::new();
+        new_chain.feed_transitions(vec![
+            (None, Some(3u8), 1),
+            (None, Some(5), 1),
+            (Some(3), Some(5), 1),
+            (Some(5), Some(10), 1),
+            (Some(5), Some(12), 1),
+        ]);
+        new_chain.feed_transitions(vec![(Some(10), None, 1), (Some(12), None, 1)]);
+        assert_eq!(chain.map, new_chain.map);
+    }
+
+    #[test]
+    fn extend() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let mut sequences = Chain::new();
+        sequences.extend(vec![vec![3u8, 5, 10], vec![5, 12]]);
+        assert_eq!(chain, sequences);
+        let mut stream = Chain::new();
+        stream.extend(vec![3u8, 5, 10]);
+        stream.extend(vec![5u8, 12]);
+        assert_eq!(chain, stream);
+    }
+
+    #[test]
+    fn feed_csv_file() {
+        let path = env::temp_dir().join("markov_feed_csv_file");
+        fs::write(&path, "3, 5, 10\n\n5,12\n").unwrap();
+        let mut chain = Chain::new();
+        chain.feed_csv_file(&path).unwrap();
+        let mut expected = Chain::new();
+        expected.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        assert_eq!(chain, expected);
+
+        fs::write(&path, "3,5\n5,x\n").unwrap();
+        let mut chain = Chain::<u8>::new();
+        let err = chain.feed_csv_file(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn add_counts() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2]);
+        let mut counts = HashMap::new();
+        counts.insert(Some(1u8), HashMap::from([(Some(2), 2), (Some(3), 1)]));
+        counts.insert(Some(3), HashMap::from([(None, 1)]));
+        chain.add_counts(&counts);
+        assert_eq!(chain.count(&1, &2), 3);
+        assert_eq!(chain.count(&1, &3), 1);
+        assert!(chain.contains_sequence(&[1, 3]));
+    }
+
+    #[test]
+    fn generate() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let v = chain.generate();
+        assert!([vec![3, 5, 10], vec![3, 5, 12], vec![5, 10], vec![5, 12]].contains(&v));
+    }
+
+    #[test]
+    fn generate_alternating() {
+        let mut a = Chain::new();
+        a.feed([1u8, 2, 3, 4]);
+        let mut b = Chain::new();
+        b.feed([1u8, 5, 3, 6]);
+        assert_eq!(Chain::generate_alternating(&a, &b), vec![1, 5, 3, 6]);
+        assert_eq!(Chain::generate_alternating(&b, &a), vec![1, 2, 3, 4]);
+        let mut c = Chain::of_order(2);
+        c.feed([7u8, 8]);
+        assert_eq!(Chain::generate_alternating(&a, &c), vec![1, 2, 3, 4]);
+        assert_eq!(Chain::generate_alternating(&c, &a), vec![7, 8]);
+        assert!(Chain::generate_alternating(&Chain::<u8>::new(), &Chain::new()).is_empty());
+    }
+
+    #[test]
+    fn generate_clamped_at() {
+        let mut chain = Chain::new();
+        chain.feed(vec!["a", "b", ".", "c"]);
+        assert_eq!(chain.generate_clamped_at(&".", false), vec!["a", "b"]);
+        assert_eq!(chain.generate_clamped_at(&".", true), vec!["a", "b", "."]);
+        assert_eq!(chain.generate_clamped_at(&"a", false), Vec::<&str>::new());
+        assert_eq!(chain.generate_clamped_at(&"x", true).len(), 4);
+    }
+
+    #[test]
+    fn generate_biased_start() {
+        let mut rng = thread_rng();
+        let mut chain = Chain::new();
+        assert!(chain.generate_biased_start(1.0, &mut rng).is_empty());
+        chain.feed([1u8]).feed([2u8, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        let long = (0..200)
+            .filter(|_| chain.generate_biased_start(4.0, &mut rng)[0] == 2)
+            .count();
+        assert!(long > 190);
+        let short = (0..200)
+            .filter(|_| chain.generate_biased_start(-4.0, &mut rng)[0] == 1)
+            .count();
+        assert!(short > 190);
+        assert_eq!(chain.openings.as_ref().unwrap()[&2], (1.0, 10.0));
+    }
+
+    #[test]
+    fn generate_into_channel() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2, 3]);
+        let (tx, rx) = mpsc::sync_channel(1);
+        thread::scope(|scope| {
+            let producer = scope.spawn(|| chain.generate_into_channel(tx, 5));
+            assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![1, 2, 3]; 5]);
+            assert!(producer.join().unwrap().is_ok());
+        });
+        let (tx, rx) = mpsc::sync_channel(1);
+        drop(rx);
+        assert_eq!(
+            chain.generate_into_channel(tx, 5).unwrap_err().0,
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn generate_backward() {
+        let mut chain = Chain::new();
+        assert!(chain.generate_backward().is_empty());
+        chain.feed([1u8, 2, 3]);
+        assert_eq!(chain.generate_backward(), vec![1, 2, 3]);
+        chain.feed([4u8, 2, 5]);
+        for _ in 0..10 {
+            assert!(chain.contains_sequence(&chain.generate_backward()));
+        }
+        let mut higher = Chain::of_order(2);
+        higher.feed([1u8, 2, 3, 4]).feed([5u8, 3, 4]);
+        for _ in 0..10 {
+            let output = higher.generate_backward();
+            assert!(output == vec![1, 2, 3, 4] || output == vec![5, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn exploring_generator() {
+        let mut chain = Chain::new();
+        for _ in 0..50 {
+            chain.feed([1u8]);
+        }
+        chain.feed([2u8]).seed(7);
+        let mut generator = chain.exploring_generator();
+        let outputs = (0..200).map(|_| generator.generate()).collect::<Vec<_>>();
+        assert!(outputs.iter().filter(|&output| *output == vec![2]).count() > 20);
+        assert!(outputs.iter().all(|output| chain.contains_sequence(output)));
+    }
+
+    #[test]
+    fn generate_with_burnin() {
+        let mut chain = Chain::of_order(2);
+        chain.feed([1u8, 2, 3, 4]);
+        assert_eq!(chain.generate_with_burnin(0), vec![1, 2, 3, 4]);
+        assert_eq!(chain.generate_with_burnin(2), vec![3, 4]);
+        assert!(chain.generate_with_burnin(4).is_empty());
+        assert!(chain.generate_with_burnin(10).is_empty());
+    }
+
+    #[test]
+    fn generate_for_seeds() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 4, 3])
+            .feed(vec![2, 2, 5])
+            .feed(vec![5, 1]);
+        let seeds = [3, 1, 4, 1, 5];
+        let outputs = chain.generate_for_seeds(&seeds);
+        assert_eq!(outputs.len(), seeds.len());
+        for (&seed, output) in seeds.iter().zip(&outputs) {
+            assert_eq!(chain.clone().seed(seed).generate(), *output);
+        }
+        assert_eq!(outputs[1], outputs[3]);
+    }
+
+    #[test]
+    fn seed() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![3u8, 5, 10])
+            .feed(vec![5, 12])
+            .feed(vec![10, 3]);
+        chain.seed(42);
+        let first = chain.iter_for(20).collect::<Vec<_>>();
+        chain.seed(42);
+        assert_eq!(chain.iter_for(20).collect::<Vec<_>>(), first);
+        chain.seed(42);
+        assert_eq!(chain.clone().iter_for(20).collect::<Vec<_>>(), first);
+    }
+
+    #[test]
+    fn rng_state() {
+        let mut chain = Chain::new();
+        chain
+            .feed([3u8, 5, 10])
+            .feed([5, 12])
+            .feed([10, 3])
+            .seed(42);
+        chain.iter_for(5).for_each(drop);
+        let snap = chain.rng_state();
+        let first = chain.iter_for(20).collect::<Vec<_>>();
+        chain.restore_rng(snap.clone());
+        assert_eq!(chain.iter_for(20).collect::<Vec<_>>(), first);
+        let mut other = chain.clone();
+        other.restore_rng(snap);
+        assert_eq!(other.iter_for(20).collect::<Vec<_>>(), first);
+        let unseeded = Chain::<u8>::new().rng_state();
+        assert!(chain.restore_rng(unseeded).rng.0.is_none());
+    }
+
+    #[test]
+    fn generate_for_higher_order() {
+        let mut chain = Chain::of_order(2);
+        chain.feed(vec![3u8, 5, 10]).feed(vec![2, 3, 5, 12]);
+        let v = chain.generate();
+        assert!([
+            vec![3, 5, 10],
+            vec![3, 5, 12],
+            vec![2, 3, 5, 10],
+            vec![2, 3, 5, 12]
+        ]
+        .contains(&v));
+    }
+
+    #[test]
+    fn sample_target_length() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.sample_target_length(&mut thread_rng()), 0);
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed_weighted(vec![4u8, 5, 6], 2.0);
+        assert_eq!(chain.sample_target_length(&mut thread_rng()), 3);
+        chain.feed(vec![7u8]);
+        for _ in 0..20 {
+            let length = chain.sample_target_length(&mut thread_rng());
+            assert!(length == 1 || length == 3);
+        }
+    }
+
+    #[test]
+    fn generate_targeting_length() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]);
+        assert_eq!(chain.generate_targeting_length(1), vec![3, 5, 10]);
+
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8; 100]);
+        assert!(chain.generate_targeting_length(1).len() < 50);
+    }
+
+    #[test]
+    fn generate_with_step_entropy() {
+        let mut chain = Chain::new();
+        assert!(chain.generate_with_step_entropy().is_empty());
+        chain.feed(vec![1u8, 2, 3]).feed(vec![1, 4, 3]);
+        let steps = chain.generate_with_step_entropy();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0], (1, 0.0));
+        assert!((steps[1].1 - 1.0).abs() < 1e-9);
+        assert_eq!(steps[2], (3, 0.0));
+    }
+
+    #[test]
+    fn generate_scored() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2, 3]).feed(vec![1, 4, 3]);
+        for _ in 0..20 {
+            let tokens =
+                chain.generate_scored(|_, to, count| if to == Some(&4) { 0.0 } else { count });
+            assert_eq!(tokens, vec![1, 2, 3]);
+        }
+        assert_eq!(chain.generate_scored(|_, _, _| 0.0), Vec::<u8>::new());
+        let tokens =
+            chain.generate_scored(|from, _, count| if from == Some(&2) { 0.0 } else { count });
+        assert!(tokens == vec![1, 2] || tokens == vec![1, 4, 3]);
+    }
+
+    #[test]
+    fn generate_within() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 4, 3])
+            .feed(vec![5, 6]);
+        let allowed = vec![1, 2, 3, 6].into_iter().collect::<HashSet<_>>();
+        for _ in 0..20 {
+            assert_eq!(chain.generate_within(&allowed), vec![1, 2, 3]);
+        }
+        let allowed = vec![1].into_iter().collect::<HashSet<_>>();
+        assert_eq!(chain.generate_within(&allowed), vec![1]);
+        assert_eq!(chain.generate_within(&HashSet::new()), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn generate_with_ranks() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2])
+            .feed(vec![1, 2])
+            .feed(vec![1, 3])
+            .feed(vec![1, 4]);
+        for _ in 0..20 {
+            let ranks = chain.generate_with_ranks();
+            assert_eq!(ranks[0], (1, 0));
+            match ranks[1] {
+                (2, rank) => assert_eq!(rank, 0),
+                (_, rank) => assert_eq!(rank, 1),
+            }
+        }
+    }
+
+    #[test]
+    fn set_deadend_policy() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2]);
+        chain.map.get_mut(&vec![Some(2)]).unwrap().clear();
+        assert_eq!(chain.generate(), vec![1, 2]);
+        chain.set_deadend_policy(DeadEndPolicy::RestartFromStart);
+        assert_eq!(chain.generate(), vec![1, 2, 1, 2, 1, 2, 1, 2]);
+        chain.set_deadend_policy(DeadEndPolicy::JumpRandom);
+        let tokens = chain.generate();
+        assert!(tokens.len() >= 2 && tokens.len() <= 8);
+        assert_eq!(tokens[..2], [1, 2]);
+        chain.set_deadend_policy(DeadEndPolicy::Terminate);
+        assert_eq!(chain.generate(), vec![1, 2]);
+    }
+
+    #[test]
+    fn generate_favor_rare() {
+        let mut chain = Chain::new();
+        assert!(chain.generate_favor_rare(1.0).is_empty());
+        for _ in 0..20 {
+            chain.feed(["the", "end"]).feed(["a", "the"]);
+        }
+        chain.feed(["a", "quokka"]);
+        let rare = (0..200)
+            .filter(|_| chain.generate_favor_rare(50.0) == vec!["a", "quokka"])
+            .count();
+        assert!(rare > 10);
+        chain.feed_weighted(["a", "wombat"], 0.0);
+        chain.feed_with_decay(["numbat"], 0.0);
+        for _ in 0..20 {
+            assert!(chain.generate_favor_rare(1.0).len() <= 2);
+        }
+    }
+
+    #[test]
+    fn generate_realistic() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.generate_realistic(), None);
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let v = chain.generate_realistic().unwrap();
+        assert!([vec![3, 5, 10], vec![3, 5, 12], vec![5, 10], vec![5, 12]].contains(&v));
+    }
+
+    #[test]
+    fn generate_top_p() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 2, 3])
+            .feed(vec![1, 2, 3])
+            .feed(vec![1, 4, 3]);
+        for _ in 0..20 {
+            assert_eq!(chain.generate_top_p(0.01), vec![1, 2, 3]);
+        }
+        let outputs = (0..200)
+            .map(|_| chain.generate_top_p(1.0))
+            .collect::<Vec<_>>();
+        assert!(outputs
+            .iter()
+            .all(|v| [vec![1, 2, 3], vec![1, 4, 3]].contains(v)));
+        assert!(outputs.contains(&vec![1, 4, 3]));
+        let mut cycle = Chain::new();
+        cycle.feed(vec![1u8, 2, 1, 2, 1, 2, 1]);
+        assert_eq!(cycle.generate_top_p(0.5), vec![1, 2, 1, 2, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn generate_top_k() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 2, 3])
+            .feed(vec![1, 4, 3])
+            .feed(vec![5, 3]);
+        for _ in 0..20 {
+            assert_eq!(chain.generate_top_k(1), vec![1, 2, 3]);
+        }
+        assert!(chain.generate_top_k(0).is_empty());
+        let mut cycle = Chain::new();
+        cycle.feed(vec![1u8, 2, 1, 2, 1, 2, 1]);
+        assert_eq!(cycle.generate_top_k(1), vec![1, 2, 1, 2, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn generate_with_sampler() {
+        struct Smallest;
+        impl Sampler<u8> for Smallest {
+            fn pick(
+                &self,
+                successors: &HashMap<Option<u8>, f64>,
+                _: &mut dyn RngCore,
+            ) -> Option<Option<u8>> {
+                successors.keys().filter_map(|&next| next).min().map(Some)
+            }
+        }
+        let mut chain = Chain::new();
+        chain
+            .feed([3u8, 1])
+            .feed([3u8, 2])
+            .feed([1u8, 5, 2])
+            .feed([1u8])
+            .feed([1u8]);
+        assert_eq!(chain.generate_with_sampler(&Smallest), vec![1, 5, 2]);
+        assert_eq!(chain.generate_with_sampler(&Greedy), vec![1]);
+        assert_eq!(chain.generate_with_sampler(&TopK(0)), Vec::<u8>::new());
+        for _ in 0..10 {
+            assert!(chain.contains_sequence(&chain.generate_with_sampler(&Proportional)));
+        }
+    }
+
+    #[test]
+    fn generate_self_avoiding() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2, 1, 2, 1, 3]);
+        for _ in 0..20 {
+            let v = chain.generate_self_avoiding();
+            assert!([vec![1, 2], vec![1, 3]].contains(&v));
+        }
+    }
+
+    #[test]
+    fn generate_diverse_set() {
+        let mut chain = Chain::new();
+        assert!(chain.generate_diverse_set(3, 0).is_empty());
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let set = chain.generate_diverse_set(10, 0);
+        assert!(set.len() <= 4);
+        for (i, a) in set.iter().enumerate() {
+            assert!(set[i + 1..].iter().all(|b| a != b));
+        }
+        assert!(chain.generate_diverse_set(10, 3).len() == 1);
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(super::edit_distance(&[1, 2, 3], &[1, 2, 3]), 0);
+        assert_eq!(super::edit_distance(&[1, 2, 3], &[1, 3]), 1);
+        assert_eq!(super::edit_distance(&[1, 2, 3], &[4, 2, 5, 6]), 3);
+        assert_eq!(super::edit_distance::<u8>(&[], &[1, 2]), 2);
+    }
+
+    #[test]
+    fn best_continuation() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3, 4])
+            .feed(vec![2, 3, 4])
+            .feed(vec![2, 5]);
+        assert_eq!(chain.best_continuation(&1, 5), vec![2, 3, 4]);
+        assert_eq!(chain.best_continuation(&1, 2), vec![2, 3]);
+        assert_eq!(chain.best_continuation(&1, 0), Vec::<u8>::new());
+        assert_eq!(chain.best_continuation(&4, 5), Vec::<u8>::new());
+        assert_eq!(chain.best_continuation(&9, 5), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn generate_including() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2, 3]).feed([1u8, 4, 3]);
+        let required = HashSet::from([4, 3]);
+        assert_eq!(
+            chain.generate_including(&required, 100),
+            Some(vec![1, 4, 3])
+        );
+        assert_eq!(chain.generate_including(&HashSet::from([2, 4]), 100), None);
+        assert_eq!(chain.generate_including(&HashSet::from([9]), 100), None);
+        assert!(chain.generate_including(&HashSet::new(), 1).is_some());
+        assert_eq!(chain.generate_including(&required, 0), None);
+    }
+
+    #[test]
+    fn generate_from_token() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let v = chain.generate_from_token(5);
+        assert!([vec![5, 10], vec![5, 12]].contains(&v));
+    }
+
+    #[test]
+    fn generate_from_unfound_token() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let v: Vec<_> = chain.generate_from_token(9);
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn end_token_distribution() {
+        let mut chain = Chain::new();
+        assert!(chain.end_token_distribution().is_empty());
+        chain
+            .feed(vec![3u8, 5, 10])
+            .feed(vec![5, 12])
+            .feed(vec![12])
+            .feed(vec![10, 12]);
+        assert_eq!(chain.end_token_distribution(), vec![(12, 3.0), (10, 1.0)]);
+    }
+
+    #[test]
+    fn filter_by_frequency() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 2, 4])
+            .feed(vec![1, 2, 3])
+            .feed(vec![5, 3]);
+        chain.filter_by_frequency(2, 3);
+        let mut expected = Chain::new();
+        expected.feed_transitions(vec![
+            (None, Some(1u8), 3),
+            (Some(1), Some(2), 3),
+            (Some(2), Some(3), 2),
+            (Some(3), None, 3),
+        ]);
+        assert_eq!(chain.map, expected.map);
+        chain.filter_by_frequency(100, 200);
+        assert!(chain.is_empty());
+    }
+
+    #[test]
+    fn clear_state() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2, 3]);
+        chain.clear_state(&2).clear_state(&9);
+        assert!(chain.map[&vec![Some(2)]].is_empty());
+        assert!(chain.has_transition(&1, &2));
+        assert_eq!(chain.generate(), vec![1, 2]);
+        chain.feed([1u8, 2, 4]);
+        assert_eq!(chain.generate(), vec![1, 2, 4]);
+
+        let mut chain = Chain::of_order(2);
+        chain
+            .enable_streaming(10)
+            .feed([1u8, 2, 3])
+            .feed([4u8, 2, 5]);
+        chain.clear_state(&2);
+        assert!(!chain.has_transition(&2, &3));
+        assert!(!chain.has_transition(&2, &5));
+        assert_eq!(chain.streaming.as_ref().unwrap().stamps.len(), 6);
+    }
+
+    #[test]
+    fn keep_top_successors() {
+        let mut chain = Chain::new();
+        chain
+            .feed([1u8, 2])
+            .feed([1u8, 2])
+            .feed([1u8, 3])
+            .feed([3u8, 4]);
+        chain.keep_top_successors(1);
+        assert_eq!(chain.generate(), vec![1, 2]);
+        assert!(!chain.map.contains_key(&vec![Some(3)]));
+
+        let mut chain = Chain::new();
+        chain.feed([1u8, 1, 1, 1, 2]);
+        chain.keep_top_successors(1);
+        assert!(chain.has_transition(&1, &1));
+        assert!(chain.has_transition(&1, &2));
+
+        let mut chain = Chain::new();
+        chain.feed([1u8, 1, 1, 1, 2]);
+        chain.keep_top_successors(0);
+        assert_eq!(chain.generate(), vec![1, 2]);
+    }
+
+    #[test]
+    fn merge_all() {
+        let mut a = Chain::new();
+        a.feed([1u8, 2]);
+        let mut b = Chain::new();
+        b.feed([1u8, 3]).feed([4u8]);
+        let merged = Chain::merge_all(&[(&a, 2.0), (&b, 0.5)]);
+        assert_eq!(merged.weight(&1, &2), 2.0);
+        assert_eq!(merged.weight(&1, &3), 0.5);
+        assert_eq!(merged.lengths.as_ref().unwrap()[&1], 0.5);
+        let mut pairwise = a.clone();
+        pairwise.merge(b.clone());
+        assert_eq!(Chain::merge_all(&[(&a, 1.0), (&b, 1.0)]).map, pairwise.map);
+        assert!(Chain::<u8>::merge_all(&[]).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_all_of_different_orders() {
+        Chain::<u8>::merge_all(&[(&Chain::of_order(1), 1.0), (&Chain::of_order(2), 1.0)]);
+    }
+
+    #[test]
+    fn map_states() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let mapped = chain.map_states(|&token| token % 2 == 0);
+        let mut expected = Chain::new();
+        expected
+            .feed(vec![false, false, true])
+            .feed(vec![false, true]);
+        assert_eq!(mapped, expected);
+    }
+
+    #[test]
+    fn partition() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 3, 2, 4]).feed(vec![2, 4]);
+        let parts = chain.partition(|&token| token % 2);
+        assert_eq!(parts.len(), 2);
+
+        let mut odd = Chain::new();
+        odd.feed_transitions(vec![
+            (None, Some(1), 1),
+            (Some(1), Some(3), 1),
+            (Some(3), None, 1),
+        ]);
+        assert_eq!(parts[&1], odd);
+
+        let mut even = Chain::new();
+        even.feed_transitions(vec![
+            (None, Some(2), 1),
+            (Some(2), Some(4), 2),
+            (Some(4), None, 2),
+        ]);
+        assert_eq!(parts[&0], even);
+    }
+
+    #[test]
+    fn enable_streaming() {
+        let mut chain = Chain::new();
+        chain.enable_streaming(3);
+        chain.feed(vec![1u8, 2]).feed(vec![3]);
+        let mut expected = Chain::new();
+        expected.feed_transitions(vec![
+            (None, Some(3u8), 1),
+            (Some(3), None, 1),
+            (Some(2), None, 1),
+        ]);
+        assert_eq!(chain.map, expected.map);
+
+        chain.feed(vec![1, 2]);
+        chain.enable_streaming(2);
+        let mut expected = Chain::new();
+        expected.feed_transitions(vec![(Some(1u8), Some(2), 1), (Some(2), None, 2)]);
+        assert_eq!(chain.map, expected.map);
+
+        chain.filter_by_frequency(0, 0);
+        assert!(chain.streaming.as_ref().unwrap().stamps.is_empty());
+        chain.feed(vec![3, 4]);
+        assert_eq!(chain.streaming.as_ref().unwrap().stamps.len(), 2);
+        assert_eq!(chain.map.values().map(HashMap::len).sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn checkpoint() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]);
+        let checkpoint = chain.checkpoint();
+        assert_eq!(chain, checkpoint);
+        chain.feed(vec![5, 12]);
+        assert!(chain != checkpoint);
+        let mut expected = Chain::new();
+        expected.feed(vec![3u8, 5, 10]);
+        assert_eq!(checkpoint, expected);
+    }
+
+    #[test]
+    fn iter() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        assert_eq!(chain.iter().size_hint().1, None);
+    }
+
+    #[test]
+    fn iter_for() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        assert_eq!(chain.iter_for(5).collect::<Vec<_>>().len(), 5);
+    }
+
+    #[test]
+    fn to_compact() {
+        let mut chain = Chain::new();
+        assert!(chain.to_compact().generate().is_empty());
+        chain.feed_str("the cat sat down");
+        assert_eq!(
+            chain.to_compact().generate(),
+            vec!["the", "cat", "sat", "down"]
+        );
+        chain.feed_str("the dog ran off");
+        let compact = chain.to_compact();
+        for _ in 0..20 {
+            assert!(chain.contains_sequence(&compact.generate()));
+        }
+        chain.seed(7);
+        let compact = chain.to_compact();
+        let first = (0..20).map(|_| compact.generate()).collect::<Vec<_>>();
+        chain.seed(7);
+        let compact = chain.to_compact();
+        assert_eq!(
+            (0..20).map(|_| compact.generate()).collect::<Vec<_>>(),
+            first
+        );
+
+        let mut chain = Chain::new();
+        chain.feed_skipgram(vec![1u8, 2, 1, 2], 1);
+        assert!(chain.to_compact().generate().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn compact_after_stashing() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2]);
+        let stashed = serde_yaml::to_string(&chain.to_compact()).unwrap();
+        let compact: super::CompactChain<u8> = serde_yaml::from_str(&stashed).unwrap();
+        assert_eq!(compact.generate(), vec![1, 2]);
+        for corrupt in [
+            "offsets: []\ntokens: []\ntargets: []\nprobabilities: []\n",
+            "offsets: [0, 2]\ntokens: [1]\ntargets: [~]\nprobabilities: [1.0]\n",
+            "offsets: [0, 1]\ntokens: [1]\ntargets: [~]\nprobabilities: []\n",
+            "offsets: [0, 1]\ntokens: [1]\ntargets: [1]\nprobabilities: [1.0]\n",
+        ] {
+            assert!(serde_yaml::from_str::<super::CompactChain<u8>>(corrupt).is_err());
+        }
+    }
+
+    #[test]
+    fn feed_paragraph() {
+        let mut chain = ParagraphChain::new();
+        assert!(chain.generate().is_empty());
+        chain.feed_paragraph(vec![vec![1u8, 2, 3], vec![4, 5], vec![6]]);
+        assert_eq!(chain.generate(), vec![vec![1, 2, 3], vec![4, 5], vec![6]]);
+        chain.feed_paragraph(vec![vec![4, 2, 5], vec![6, 7]]);
+        for _ in 0..20 {
+            for sentence in chain.generate() {
+                assert!(chain.word_chain().contains_sequence(&sentence));
+            }
+        }
+        assert!(chain
+            .sentence_chain()
+            .contains_sequence(&[vec![4, 2, 5], vec![6, 7]]));
+    }
+
+    #[test]
+    fn k_step_probability() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2, 3]).feed([1u8, 3]);
+        assert_eq!(chain.k_step_probability(&1, &1, 0), 1.0);
+        assert_eq!(chain.k_step_probability(&1, &3, 0), 0.0);
+        assert_eq!(chain.k_step_probability(&1, &2, 1), 0.5);
+        assert_eq!(chain.k_step_probability(&1, &3, 1), 0.5);
+        assert_eq!(chain.k_step_probability(&1, &3, 2), 0.5);
+        assert_eq!(chain.k_step_probability(&3, &1, 2), 1.0);
+        assert_eq!(chain.k_step_probability(&9, &1, 2), 0.0);
+        let mut higher = Chain::of_order(2);
+        higher.feed([1u8, 2, 3]).feed([4u8, 2, 5]);
+        assert_eq!(higher.k_step_probability(&2, &3, 1), 0.5);
+    }
+
+    #[test]
+    fn mixing_time() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.mixing_time(0.1), 0);
+        chain.feed([1u8]).feed([1u8, 1]);
+        assert_eq!(chain.mixing_time(0.5), 1);
+        assert_eq!(chain.mixing_time(0.1), 5);
+        let mut periodic = Chain::new();
+        periodic.feed([1u8, 2]);
+        assert_eq!(periodic.mixing_time(0.1), usize::MAX);
+    }
+
+    #[test]
+    fn advance() {
+        let mut chain = Chain::of_order(2);
+        chain.feed(vec![1u8, 2, 3]);
+        let mut state = chain.begin_generation();
+        let mut rng = thread_rng();
+        assert_eq!(chain.advance(&mut state, &mut rng), Some(1));
+        assert_eq!(chain.advance(&mut state, &mut rng), Some(2));
+        assert_eq!(chain.advance(&mut state, &mut rng), Some(3));
+        assert!(!state.is_finished());
+        assert_eq!(chain.advance(&mut state, &mut rng), None);
+        assert!(state.is_finished());
+        assert_eq!(chain.advance(&mut state, &mut rng), None);
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn advance_after_stashing() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2]);
+        let mut state = chain.begin_generation();
+        let mut rng = thread_rng();
+        assert_eq!(chain.advance(&mut state, &mut rng), Some(1));
+        let stashed = serde_yaml::to_string(&state).unwrap();
+        let mut state = serde_yaml::from_str(&stashed).unwrap();
+        assert_eq!(chain.advance(&mut state, &mut rng), Some(2));
+        assert_eq!(chain.advance(&mut state, &mut rng), None);
+    }
+
+    #[test]
+    fn words() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats and dogs");
+        assert_eq!(
+            chain.words().collect::<Vec<_>>(),
+            vec!["I", "like", "cats", "and", "dogs"]
+        );
+        assert_eq!(chain.words().take(2).collect::<Vec<_>>(), vec!["I", "like"]);
+    }
+
+    #[test]
+    fn random_walk() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.random_walk().next(), None);
+        chain.feed(vec![3u8, 5, 10]);
+        assert_eq!(
+            chain.random_walk().take(7).collect::<Vec<_>>(),
+            vec![3, 5, 10, 3, 5, 10, 3]
+        );
+        let mut chain = Chain::new();
+        chain.feed_transitions(vec![(None::<u8>, None, 2)]);
+        assert_eq!(chain.random_walk().next(), None);
+    }
+
+    #[test]
+    fn random_walk_sentences() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.random_walk().sentences().next(), None);
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        for sentence in chain.random_walk().sentences().take(5) {
+            assert!([vec![3, 5, 10], vec![3, 5, 12], vec![5, 10], vec![5, 12]].contains(&sentence));
+        }
+
+        let mut chain = Chain::new();
+        chain.feed_skipgram(vec![1u8, 2, 1, 2], 1);
+        for sentence in chain.random_walk().sentences().take(5) {
+            assert!(!sentence.is_empty() && sentence.iter().all(|&token| token == 2));
+        }
+    }
+
+    #[test]
+    fn from_text() {
+        let chain = Chain::from_text("  Sentence one.Sentence\ttwo!!  What?! ");
+        let mut expected = Chain::new();
+        expected
+            .feed_str("Sentence one")
+            .feed_str("Sentence two")
+            .feed_str("What");
+        assert_eq!(chain, expected);
+        assert!(Chain::from_text(" ...? ").is_empty());
+    }
+
+    #[test]
+    fn feed_str() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats and dogs");
+    }
+
+    #[test]
+    fn feed_str_smart() {
+        let mut chain = Chain::new();
+        chain.feed_str_smart("It's a well-known fact, isn't it?");
+        let mut expected = Chain::new();
+        expected.feed_str("It's a well-known fact , isn't it ?");
+        assert_eq!(chain, expected);
+        let mut chain = Chain::new();
+        chain.feed_str_smart(" 'tis the dogs' -- toys\tre-used ");
+        let mut expected = Chain::new();
+        expected.feed_str("' tis the dogs ' - - toys re-used");
+        assert_eq!(chain, expected);
+    }
+
+    #[test]
+    fn feed_dir() {
+        let dir = env::temp_dir().join("markov_feed_dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.txt"), "the cat sat\n").unwrap();
+        fs::write(dir.join("b.md"), "a dog ran\n").unwrap();
+        fs::write(dir.join("c.txt"), [0xffu8, 0xfe]).unwrap();
+        fs::write(dir.join("nested").join("d.txt"), "a bird flew\n").unwrap();
+
+        let mut chain = Chain::new();
+        let err = chain.feed_dir(&dir, Some("txt")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("c.txt"));
+        assert_eq!(chain.generate_str(), "the cat sat");
+
+        fs::remove_file(dir.join("c.txt")).unwrap();
+        let mut chain = Chain::new();
+        chain.feed_dir(&dir, None).unwrap();
+        assert!(chain.contains(&"dog".to_owned()));
+        assert!(chain.contains(&"cat".to_owned()));
+        assert!(!chain.contains(&"bird".to_owned()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn feed_file_dedup() {
+        let path = env::temp_dir().join("markov_feed_file_dedup");
+        fs::write(
+            &path,
+            "I like cats\nI like cats\ni like, cats!\nI like dogs\n",
+        )
+        .unwrap();
+
+        let mut chain = Chain::new();
+        chain.feed_file_dedup(&path, DedupMode::Exact).unwrap();
+        let mut exact = Chain::new();
+        exact
+            .feed_str("I like cats")
+            .feed_str("i like, cats!")
+            .feed_str("I like dogs");
+        assert_eq!(chain, exact);
+
+        let mut chain = Chain::new();
+        chain.feed_file_dedup(&path, DedupMode::Normalized).unwrap();
+        let mut normalized = Chain::new();
+        normalized.feed_str("I like cats").feed_str("I like dogs");
+        assert_eq!(chain, normalized);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn generate_str() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats").feed_str("I hate cats");
+        assert!(["I like cats", "I hate cats"].contains(&&chain.generate_str()[..]));
+    }
+
+    #[test]
+    fn generate_str_terminated() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats");
+        assert_eq!(chain.generate_str_terminated("."), "I like cats.");
+        assert_eq!(chain.generate_str_terminated("!"), "I like cats!");
+
+        let mut chain = Chain::new();
+        chain.feed_str("do you like cats?");
+        assert_eq!(chain.generate_str_terminated("."), "do you like cats?");
+    }
+
+    #[test]
+    fn generate_str_cased() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.generate_str_cased(), "");
+        chain.ignore_case();
+        chain
+            .feed_str("the NASA launch")
+            .feed_str("The nasa launch")
+            .feed_str("the NASA launch");
+        assert_eq!(chain.generate_str_cased(), "The NASA launch");
+    }
+
+    #[test]
+    fn generate_paragraph() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.generate_paragraph(3, " | "), "");
+        chain.feed_str("the cat sat.");
+        assert_eq!(
+            chain.generate_paragraph(3, " | "),
+            "the cat sat. | the cat sat. | the cat sat."
+        );
+        assert_eq!(chain.generate_paragraph(0, " | "), "");
+    }
+
+    #[test]
+    fn generate_between() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3, 4, 5])
+            .feed(vec![1, 6, 5])
+            .feed(vec![7, 3, 8]);
+        for _ in 0..20 {
+            assert_eq!(chain.generate_between(1, 5, 3), Some(vec![1, 6, 5]));
+            let tokens = chain.generate_between(1, 5, 10).unwrap();
+            assert!(tokens == vec![1, 6, 5] || tokens == vec![1, 2, 3, 4, 5]);
+            assert_eq!(chain.generate_between(7, 4, 10), Some(vec![7, 3, 4]));
+        }
+        assert_eq!(chain.generate_between(1, 5, 2), None);
+        assert_eq!(chain.generate_between(9, 5, 10), None);
+        assert_eq!(chain.generate_between(1, 7, 10), None);
+        assert_eq!(chain.generate_between(1, 1, 10), Some(vec![1]));
+    }
+
+    #[test]
+    fn generate_from_tokens() {
+        let mut chain = Chain::of_order(2);
+        chain.feed(vec![1u8, 2, 3, 4]);
+        assert_eq!(chain.generate_from_tokens(vec![1]), vec![1, 2, 3, 4]);
+        assert_eq!(chain.generate_from_tokens(vec![9, 2, 3]), vec![9, 2, 3, 4]);
+        assert_eq!(chain.generate_from_tokens(vec![2]), Vec::<u8>::new());
+        assert_eq!(chain.generate_from_tokens(Vec::new()), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn random_operations() {
+        let mut rng = StdRng::seed_from_u64(143);
+        for _ in 0..50 {
+            let mut chain = Chain::of_order(rng.gen_range(1..4));
+            for _ in 0..50 {
+                let len = rng.gen_range(0..5);
+                let tokens = (0..len).map(|_| rng.gen_range(0..6u8)).collect::<Vec<_>>();
+                let token = rng.gen_range(0..8u8);
+                let op = rng.gen_range(0..24);
+                match op {
+                    0 => {
+                        chain.feed(&tokens);
+                    }
+                    1 => {
+                        chain.feed_weighted(&tokens, 0.0);
+                    }
+                    2 => {
+                        chain.feed_tagged(u64::from(token), &tokens);
+                    }
+                    3 => {
+                        chain.remove_tagged(u64::from(token));
+                    }
+                    4 => {
+                        chain.filter_by_frequency(rng.gen_range(0..3), rng.gen_range(0..5));
+                    }
+                    5 => {
+                        chain.generate();
+                        chain.generate_most_probable();
+                    }
+                    6 => {
+                        chain.generate_from_token(token);
+                        chain.generate_from_tokens(tokens.clone());
+                    }
+                    7 => {
+                        chain.generate_top_k(0);
+                        chain.generate_top_k(usize::MAX);
+                        chain.generate_top_k(1);
+                        chain.generate_top_p(1.0);
+                        chain.generate_top_p(0.1);
+                    }
+                    8 => {
+                        chain.generate_targeting_length(rng.gen_range(0..5));
+                        chain.generate_self_avoiding();
+                    }
+                    9 => {
+                        chain.generate_realistic();
+                        chain.generate_diverse_set(3, 1);
+                        chain.generate_favor_rare(1.0);
+                        chain.to_compact().generate();
+                    }
+                    10 => {
+                        chain.random_walk().take(10).count();
+                        chain.random_walk().sentences().take(3).count();
+                    }
+                    11 => {
+                        chain.best_continuation(&token, 3);
+                        chain.generate_with_step_entropy();
+                    }
+                    12 => {
+                        chain.sequence_probability(&tokens);
+                        chain.contains_sequence(&tokens);
+                        chain.perplexity(&[tokens]);
+                    }
+                    13 => {
+                        chain.partition(|&token| token % 2);
+                        chain.end_token_distribution();
+                        chain.out_degree_histogram();
+                    }
+                    14 => {
+                        let other = chain.clone();
+                        chain.merge(other);
+                        chain.kl_divergence(&chain.clone());
+                    }
+                    15 => {
+                        chain.feed_with_decay(&tokens, rng.gen_range(0.0..=1.0));
+                    }
+                    16 => {
+                        let mut wrapped = vec![None];
+                        wrapped.extend(tokens.iter().cloned().map(Some));
+                        wrapped.push(None);
+                        let triples = wrapped
+                            .windows(2)
+                            .map(|p| (p[0], p[1], usize::from(token)))
+                            .collect::<Vec<_>>();
+                        if chain.order() == 1 {
+                            chain.feed_transitions(triples.clone());
+                            let mut counts = HashMap::<_, HashMap<_, _>>::new();
+                            for (from, to, count) in triples {
+                                *counts.entry(from).or_default().entry(to).or_insert(0) += count;
+                            }
+                            chain.add_counts(&counts);
+                        }
+                    }
+                    17 => {
+                        let mut map = HashMap::<_, HashMap<_, _>>::new();
+                        let mut wrapped = vec![6u8];
+                        wrapped.extend(&tokens);
+                        wrapped.push(7);
+                        for p in wrapped.windows(2) {
+                            map.entry(p[0]).or_default().insert(p[1], f64::from(token));
+                        }
+                        if let Ok(built) = Chain::from_map(map, 6, 7) {
+                            built.generate();
+                            if chain.order() == 1 {
+                                chain.merge(built);
+                            }
+                        }
+                    }
+                    18 => {
+                        chain.feed_chunked(&tokens, rng.gen_range(1..4));
+                        chain.feed_skipgram(&tokens, rng.gen_range(0..3));
+                    }
+                    19 => {
+                        chain.generate_beam(rng.gen_range(0..4), rng.gen_range(0..6));
+                        chain.most_probable_path_of_length(rng.gen_range(0..6));
+                        chain.top_phrases(rng.gen_range(0..4), 3);
+                    }
+                    20 => {
+                        chain.keep_top_successors(rng.gen_range(0..3));
+                        chain.clear_state(&token);
+                    }
+                    21 => {
+                        let other = tokens.first().cloned().unwrap_or(token);
+                        chain.k_step_probability(&token, &other, rng.gen_range(0..4));
+                        chain.termination_probability(rng.gen_range(0..6));
+                        chain.successor_similarity(&token, &other);
+                        chain.surprisal(&token, &other);
+                        chain.novelty_score(&tokens);
+                        chain.bigram_density();
+                        chain.validate().ok();
+                    }
+                    22 => {
+                        let mut generator = chain.exploring_generator();
+                        generator.generate();
+                        generator.generate();
+                        chain.generate_with_sampler(&Greedy);
+                    }
+                    _ => {
+                        chain.random_state(&mut rng);
+                        chain.sample_target_length(&mut rng);
+                        chain.is_empty();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn generate_str_from_token() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats").feed_str("cats are cute");
+        assert!(["cats", "cats are cute"].contains(&&chain.generate_str_from_token("cats")[..]));
+    }
+
+    #[test]
+    fn generate_str_from_token_higher_order() {
+        let mut chain = Chain::of_order(2);
+        chain.feed_str("I like cats").feed_str("cats are cute");
+        println!("{:?}", chain.generate_str_from_token("cats"));
+        assert!(["cats", "cats are cute"].contains(&&chain.generate_str_from_token("cats")[..]));
     }
-}
 
-#[cfg(feature = "yaml")]
-impl<T> Chain<T>
-where
-    T: Chainable + DeserializeOwned,
-{
-    /// Loads a chain from the specified path.
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Chain<T>> {
-        let mut file = File::open(&path)?;
-        let mut data = String::new();
-        file.read_to_string(&mut data)?;
+    #[test]
+    fn generate_str_from_unfound_token() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats").feed_str("cats are cute");
+        assert_eq!(chain.generate_str_from_token("test"), "");
+    }
 
-        yaml::from_str(&data).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    #[test]
+    fn str_iter() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats and I like dogs");
+        assert_eq!(chain.str_iter().size_hint().1, None);
     }
-}
 
-impl Chain<String> {
-    /// Feeds a string of text into the chain.
-    pub fn feed_str(&mut self, string: &str) -> &mut Chain<String> {
-        self.feed(&string.split(' ').map(|s| s.to_owned()).collect::<Vec<_>>())
+    #[test]
+    fn str_iter_for() {
+        let mut chain = Chain::new();
+        chain.feed_str("I like cats and I like dogs");
+        assert_eq!(chain.str_iter_for(5).collect::<Vec<_>>().len(), 5);
     }
 
-    /// Feeds a properly formatted file into the chain. This file should be formatted such that
-    /// each line is a new sentence. Punctuation may be included if it is desired.
-    pub fn feed_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Chain<String>> {
-        let reader = BufReader::new(File::open(path)?);
-        for line in reader.lines() {
-            let line = line?;
-            let words = line
-                .split_whitespace()
-                .filter(|word| !word.is_empty())
-                .map(|s| s.to_owned())
-                .collect::<Vec<_>>();
-            self.feed(&words);
-        }
-        Ok(self)
+    #[test]
+    fn ignore_case() {
+        let mut chain = Chain::new();
+        chain.ignore_case();
+        assert!(chain.is_case_insensitive());
+        chain.feed_str("I like Cats").feed_str("i like cats");
+        let mut folded = Chain::new();
+        folded.feed_str("i like cats").feed_str("i like cats");
+        assert_eq!(chain.map, folded.map);
+        assert!(!chain.generate_str_from_token("CATS").is_empty());
     }
 
-    /// Converts the output of `generate(...)` on a String chain to a single String.
-    fn vec_to_string(vec: Vec<String>) -> String {
-        let mut ret = String::new();
-        for s in &vec {
-            ret.push_str(s);
-            ret.push(' ');
+    #[test]
+    fn ignore_case_most_common_surface_form() {
+        let mut chain = Chain::new();
+        chain.ignore_case();
+        for _ in 0..10 {
+            chain.feed_str("I like Apple");
         }
-        let len = ret.len();
-        if len > 0 {
-            ret.truncate(len - 1);
+        for _ in 0..2 {
+            chain.feed_str("i like apple");
         }
-        ret
+        assert_eq!(chain.generate_str(), "I like Apple");
+        assert_eq!(chain.generate(), vec!["I", "like", "Apple"]);
     }
 
-    /// Generates a random string of text.
-    pub fn generate_str(&self) -> String {
-        Chain::vec_to_string(self.generate())
+    #[test]
+    fn print_histogram() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.print_histogram(5), "");
+        chain
+            .feed_str("the cat")
+            .feed_str("the cat")
+            .feed_str("a cat");
+        let expected = format!(
+            "cat -> <end>   {} 3\n\
+             <start> -> the {} 2\n\
+             the -> cat     {} 2\n",
+            "#".repeat(40),
+            "#".repeat(27),
+            "#".repeat(27)
+        );
+        assert_eq!(chain.print_histogram(3), expected);
+        assert_eq!(chain.print_histogram(100).lines().count(), 5);
     }
 
-    /// Generates a random string of text starting with the desired token. This returns an empty
-    /// string if the token is not found.
-    pub fn generate_str_from_token(&self, string: &str) -> String {
-        Chain::vec_to_string(self.generate_from_token(string.to_owned()))
-    }
+    #[test]
+    fn save_tsv_then_load_tsv() {
+        let mut chain = Chain::of_order(2);
+        chain
+            .feed(vec!["a".to_owned(), "b\tc".to_owned(), "\\N".to_owned()])
+            .feed(vec!["".to_owned(), "line\nbreak".to_owned()])
+            .feed_weighted(vec!["a".to_owned()], 0.25);
+        let mut tsv = Vec::new();
+        chain.save_tsv(&mut tsv).unwrap();
+        assert!(String::from_utf8(tsv.clone())
+            .unwrap()
+            .contains("b\\tc\t\\\\N\t\\N\t1\n"));
+        let loaded = Chain::<String>::load_tsv(&tsv[..]).unwrap();
+        assert_eq!(loaded.order(), 2);
+        assert_eq!(loaded.map, chain.map);
 
-    /// Generates a random string of text starting with the desired tokens. This returns an empty
-    /// string if the tokens are not found.
-    pub fn generate_str_from_tokens(&self, string: &str) -> String {
-        Chain::vec_to_string(self.generate_from_tokens(string.split_whitespace().map(|s| s.to_owned()).collect::<Vec<String>>()))
-    }
+        let twice = [&tsv[..], &tsv[..]].concat();
+        let loaded = Chain::<String>::load_tsv(&twice[..]).unwrap();
+        let state = vec![Some("a".to_owned()), Some("b\tc".to_owned())];
+        assert_eq!(loaded.map[&state][&Some("\\N".to_owned())], 2.0);
 
-    /// Produces an infinite iterator of generated strings.
-    pub fn str_iter(&self) -> InfiniteChainStringIterator {
-        let vec_to_string: fn(Vec<String>) -> String = Chain::vec_to_string;
-        self.iter().map(vec_to_string)
+        assert_eq!(Chain::<u8>::load_tsv(&b""[..]).unwrap(), Chain::new());
+        let empty = Chain::<u8>::of_order(2);
+        let mut tsv = Vec::new();
+        empty.save_tsv(&mut tsv).unwrap();
+        assert_eq!(Chain::<u8>::load_tsv(&tsv[..]).unwrap(), empty);
+
+        let mut chain = Chain::of_order(3);
+        chain
+            .feed(vec![1u8, 2, 3, 4])
+            .feed(vec![2, 3])
+            .clear_state(&3);
+        let mut tsv = Vec::new();
+        chain.save_tsv(&mut tsv).unwrap();
+        let loaded = Chain::<u8>::load_tsv(&tsv[..]).unwrap();
+        assert_eq!(loaded.order(), 3);
+        assert_eq!(loaded.map, chain.map);
+
+        for malformed in [
+            "1\t2\n",
+            "1\t2\tx\n",
+            "1\t2\t3\n1\t2\t3\t4\n",
+            "1\t\\x\t3\n",
+            "\\N\t1\tNaN\n1\t\\N\t-5\n",
+            "\\N\t1\tinf\n",
+            "1\t\\N\t2\t1\n",
+            "\\order\t0\n",
+            "\\order\t2\n1\t2\t1\n",
+            "\\order\t1\n\\order\t2\n",
+        ] {
+            let err = Chain::<u8>::load_tsv(malformed.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
     }
 
-    /// Produces a sized iterator of generated strings.
-    pub fn str_iter_for(&self, size: usize) -> SizedChainStringIterator {
-        let vec_to_string: fn(Vec<String>) -> String = Chain::vec_to_string;
-        self.iter_for(size).map(vec_to_string)
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn save_then_load() {
+        let mut chain = Chain::of_order(2);
+        chain.feed_str("I like cats and I like dogs");
+        chain.save("test.yaml").unwrap();
+
+        let new_chain: Chain<String> = Chain::load("test.yaml").unwrap();
+        assert_eq!(chain, new_chain);
+        assert_eq!(new_chain.order(), 2);
     }
-}
 
-/// A sized iterator over a Markov chain of strings.
-pub type SizedChainStringIterator<'a> =
-    Map<SizedChainIterator<'a, String>, fn(Vec<String>) -> String>;
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn save_then_load_streaming() {
+        let path = env::temp_dir().join("markov_save_then_load_streaming.yaml");
+        let mut chain = Chain::of_order(2);
+        chain.enable_streaming(10);
+        chain.feed_str("I like cats and I like dogs");
+        chain.save(&path).unwrap();
 
-/// A sized iterator over a Markov chain.
-pub struct SizedChainIterator<'a, T: Chainable + 'a> {
-    chain: &'a Chain<T>,
-    size: usize,
-}
+        let new_chain: Chain<String> = Chain::load(&path).unwrap();
+        assert_eq!(chain, new_chain);
+        fs::remove_file(&path).unwrap();
+    }
 
-impl<'a, T> Iterator for SizedChainIterator<'a, T>
-where
-    T: Chainable + 'a,
-{
-    type Item = Vec<T>;
-    fn next(&mut self) -> Option<Vec<T>> {
-        if self.size > 0 {
-            self.size -= 1;
-            Some(self.chain.generate())
-        } else {
-            None
+    #[test]
+    fn random_state() {
+        let mut rng = thread_rng();
+        let mut chain = Chain::new();
+        assert_eq!(chain.random_state(&mut rng), None);
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        for _ in 0..10 {
+            assert!([3, 5, 10, 12].contains(&chain.random_state(&mut rng).unwrap()));
         }
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.size, Some(self.size))
+    #[test]
+    fn step_no_end() {
+        let mut rng = thread_rng();
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2]).feed([1u8]).feed([1u8]).feed([3u8]);
+        for _ in 0..10 {
+            assert_eq!(chain.step_no_end(&1, &mut rng), Some(2));
+        }
+        assert_eq!(chain.step_no_end(&3, &mut rng), None);
+        assert_eq!(chain.step_no_end(&9, &mut rng), None);
     }
-}
 
-/// An infinite iterator over a Markov chain of strings.
-pub type InfiniteChainStringIterator<'a> =
-    Map<InfiniteChainIterator<'a, String>, fn(Vec<String>) -> String>;
+    #[test]
+    fn successor_concentration() {
+        let mut chain = Chain::new();
+        chain.feed([1u8, 2]).feed([1u8, 3]).feed([4u8, 5]);
+        assert_eq!(chain.successor_concentration(&1), 0.0);
+        assert_eq!(chain.successor_concentration(&4), 0.0);
+        assert_eq!(chain.successor_concentration(&9), 0.0);
+        chain.feed([1u8, 2]).feed([1u8, 2]);
+        assert_eq!(chain.successor_concentration(&1), 0.5);
+        for _ in 0..100 {
+            chain.feed([1u8, 2]);
+        }
+        assert!(chain.successor_concentration(&1) > 0.95);
+    }
 
-/// An infinite iterator over a Markov chain.
-pub struct InfiniteChainIterator<'a, T: Chainable + 'a> {
-    chain: &'a Chain<T>,
-}
+    #[test]
+    fn generate_for_seeds_with() {
+        let mut chain = Chain::new();
+        chain.feed_str("the cat sat").feed_str("the dog ran off");
+        let seeds = [1, 2, 3, 4];
+        let outputs = chain.generate_for_seeds_with::<SmallRng>(&seeds);
+        assert_eq!(outputs.len(), 4);
+        assert_eq!(outputs, chain.generate_for_seeds_with::<SmallRng>(&seeds));
+        assert_eq!(
+            chain.generate_for_seeds_with::<StdRng>(&seeds),
+            chain.generate_for_seeds(&seeds)
+        );
+    }
 
-impl<'a, T> Iterator for InfiniteChainIterator<'a, T>
-where
-    T: Chainable + 'a,
-{
-    type Item = Vec<T>;
-    fn next(&mut self) -> Option<Vec<T>> {
-        Some(self.chain.generate())
+    #[test]
+    fn generate_most_probable() {
+        let mut chain = Chain::new();
+        assert!(chain.generate_most_probable().is_empty());
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 2, 3])
+            .feed(vec![1, 4, 3])
+            .feed(vec![5, 3]);
+        assert_eq!(chain.generate_most_probable(), vec![1, 2, 3]);
+
+        let mut chain = Chain::new();
+        chain.feed(vec![2u8, 3]).feed(vec![1, 3]);
+        let first = chain.generate_most_probable();
+        assert_eq!(first, vec![1, 3]);
+        assert_eq!(chain.generate_most_probable(), first);
     }
-}
 
-/// A collection of states for the Markov chain.
-trait States<T: PartialEq> {
-    /// Adds a state to this states collection.
-    fn add(&mut self, token: Token<T>, count: usize);
-    /// Gets the next state from this collection of states.
-    fn next(&self) -> Token<T>;
-}
+    #[test]
+    fn generate_beam() {
+        let mut chain = Chain::new();
+        chain
+            .feed([1u8, 3])
+            .feed([1, 4])
+            .feed([1, 5])
+            .feed([2, 6])
+            .feed([2, 6]);
+        assert_eq!(chain.generate_beam(1, 10), vec![1, 3]);
+        assert_eq!(chain.generate_beam(2, 10), vec![2, 6]);
+        assert_eq!(
+            chain.generate_beam(usize::MAX, 10),
+            chain.generate_most_probable()
+        );
+        assert!(chain.generate_beam(2, 1).is_empty());
+        assert!(Chain::<u8>::new().generate_beam(2, 10).is_empty());
+    }
 
-impl<T> States<T> for HashMap<Token<T>, usize>
-where
-    T: Chainable,
-{
-    fn add(&mut self, token: Token<T>, count: usize) {
-        match self.entry(token) {
-            Occupied(mut e) => *e.get_mut() += count,
-            Vacant(e) => {
-                e.insert(count);
-            }
-        }
+    #[test]
+    fn most_probable_path_of_length() {
+        let mut chain = Chain::new();
+        chain
+            .feed([1u8, 2, 3])
+            .feed([1, 2, 3])
+            .feed([1, 4])
+            .feed([5, 6, 7]);
+        assert_eq!(chain.most_probable_path_of_length(2), vec![1, 2]);
+        assert_eq!(chain.most_probable_path_of_length(3), vec![1, 2, 3]);
+        assert!(chain.most_probable_path_of_length(4).is_empty());
+        assert!(chain.most_probable_path_of_length(0).is_empty());
+        chain.feed([6u8, 8]);
+        assert_eq!(chain.most_probable_path_of_length(1), vec![1]);
     }
 
-    fn next(&self) -> Token<T> {
-        let mut sum = 0;
-        for &value in self.values() {
-            sum += value;
-        }
-        let mut rng = thread_rng();
-        let cap = rng.gen_range(0..sum);
-        sum = 0;
-        for (key, &value) in self.iter() {
-            sum += value;
-            if sum > cap {
-                return key.clone();
-            }
-        }
-        unreachable!("The random number generator failed.")
+    #[test]
+    fn top_phrases() {
+        let mut chain = Chain::new();
+        assert!(chain.top_phrases(2, 3).is_empty());
+        chain
+            .feed([1u8, 2, 3])
+            .feed([1u8, 2, 4])
+            .feed([2u8, 4])
+            .feed([1u8, 5]);
+        assert_eq!(
+            chain.top_phrases(2, 3),
+            vec![
+                (vec![1, 2], 2.0 / 3.0),
+                (vec![2, 4], 2.0 / 3.0),
+                (vec![1, 5], 1.0 / 3.0)
+            ]
+        );
+        let phrases = chain.top_phrases(3, 10);
+        assert_eq!(phrases[0].0, vec![1, 2, 4]);
+        assert_eq!(phrases.len(), 2);
+        assert!(chain.top_phrases(0, 10).is_empty());
+        assert!(chain.top_phrases(2, 0).is_empty());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::Chain;
+    #[test]
+    fn sorted_vocabulary() {
+        let mut chain = Chain::new();
+        assert!(chain.sorted_vocabulary().is_empty());
+        chain.feed(vec![10u8, 5, 3]).feed(vec![12, 5]);
+        assert_eq!(chain.sorted_vocabulary(), vec![3, 5, 10, 12]);
+    }
 
     #[test]
-    fn new() {
-        Chain::<u8>::new();
-        Chain::<String>::new();
+    fn kl_divergence() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2, 3]).feed(vec![1, 3]);
+        assert_eq!(chain.kl_divergence(&chain.clone()), 0.0);
+        assert_eq!(chain.kl_divergence(&Chain::new()), 0.0);
+
+        let mut close = Chain::new();
+        close
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 3])
+            .feed(vec![1, 3]);
+        let mut far = Chain::new();
+        far.feed(vec![1u8, 4]).feed(vec![2, 1]);
+        let near = chain.kl_divergence(&close);
+        assert!(near > 0.0);
+        assert!(near < chain.kl_divergence(&far));
     }
 
     #[test]
-    fn is_empty() {
+    fn contains_sequence() {
         let mut chain = Chain::new();
-        assert!(chain.is_empty());
-        chain.feed(vec![1u8, 2, 3]);
-        assert!(!chain.is_empty());
+        chain.feed(vec![1u8, 2, 3]).feed(vec![4, 2, 5]);
+        assert!(chain.contains_sequence(&[1, 2, 3]));
+        assert!(chain.contains_sequence(&[1, 2, 5]));
+        assert!(!chain.contains_sequence(&[1, 2]));
+        assert!(!chain.contains_sequence(&[2, 3]));
+        assert!(!chain.contains_sequence(&[]));
+        for _ in 0..10 {
+            assert!(chain.contains_sequence(&chain.generate()));
+        }
     }
 
     #[test]
-    fn feed() {
+    fn mutually_reachable() {
         let mut chain = Chain::new();
-        chain.feed(vec![3, 5, 10]).feed(vec![5, 12]);
+        chain.feed([1u8, 2, 3, 1, 4]).feed([5u8]);
+        assert!(chain.mutually_reachable(&1, &3));
+        assert!(chain.mutually_reachable(&2, &1));
+        assert!(!chain.mutually_reachable(&1, &4));
+        assert!(!chain.mutually_reachable(&4, &5));
+        assert!(chain.mutually_reachable(&5, &5));
+        assert!(!chain.mutually_reachable(&9, &9));
     }
 
     #[test]
-    fn generate() {
+    fn out_degree_histogram() {
         let mut chain = Chain::new();
-        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
-        let v = chain.generate();
-        assert!([vec![3, 5, 10], vec![3, 5, 12], vec![5, 10], vec![5, 12]].contains(&v));
+        assert!(chain.out_degree_histogram().is_empty());
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![1, 4, 3])
+            .feed(vec![1, 2]);
+        let histogram = chain.out_degree_histogram();
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram[&1], 3);
+        assert_eq!(histogram[&2], 2);
     }
 
     #[test]
-    fn generate_for_higher_order() {
+    fn bigram_density() {
         let mut chain = Chain::of_order(2);
-        chain.feed(vec![3u8, 5, 10]).feed(vec![2, 3, 5, 12]);
-        let v = chain.generate();
-        assert!([
-            vec![3, 5, 10],
-            vec![3, 5, 12],
-            vec![2, 3, 5, 10],
-            vec![2, 3, 5, 12]
-        ]
-        .contains(&v));
+        assert_eq!(chain.bigram_density(), 0.0);
+        chain.feed([1u8, 2, 3]).feed([2, 2, 3]).feed([1, 2, 3]);
+        assert_eq!(chain.bigram_density(), 3.0 / 9.0);
     }
 
     #[test]
-    fn generate_from_token() {
+    fn successor_similarity() {
         let mut chain = Chain::new();
-        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
-        let v = chain.generate_from_token(5);
-        assert!([vec![5, 10], vec![5, 12]].contains(&v));
+        chain.feed([1u8, 3]).feed([2, 3]).feed([2, 3]).feed([4, 5]);
+        assert!((chain.successor_similarity(&1, &2) - 1.0).abs() < 1e-9);
+        assert_eq!(chain.successor_similarity(&1, &4), 0.0);
+        assert_eq!(chain.successor_similarity(&1, &9), 0.0);
+        chain.feed([1u8, 5]);
+        assert!((chain.successor_similarity(&1, &2) - 0.5f64.sqrt()).abs() < 1e-9);
     }
 
     #[test]
-    fn generate_from_unfound_token() {
+    fn longest_path() {
         let mut chain = Chain::new();
-        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
-        let v: Vec<_> = chain.generate_from_token(9);
-        assert!(v.is_empty());
+        assert!(chain.longest_path().is_empty());
+        chain
+            .feed(vec![1u8, 2, 3])
+            .feed(vec![2, 4, 2, 5])
+            .feed(vec![3, 1]);
+        assert_eq!(chain.longest_path(), vec![3, 1, 2, 5]);
     }
 
     #[test]
-    fn iter() {
+    fn enumerate_paths() {
         let mut chain = Chain::new();
-        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
-        assert_eq!(chain.iter().size_hint().1, None);
+        assert!(chain.enumerate_paths(5).is_empty());
+        chain.feed(vec![1u8, 2]).feed(vec![1, 1, 3]);
+        let mut paths = chain.enumerate_paths(3);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![vec![1, 1, 2], vec![1, 1, 3], vec![1, 2], vec![1, 3]]
+        );
+        assert!(chain.enumerate_paths(0).is_empty());
     }
 
     #[test]
-    fn iter_for() {
+    fn transition_matrix() {
         let mut chain = Chain::new();
-        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
-        assert_eq!(chain.iter_for(5).collect::<Vec<_>>().len(), 5);
+        chain.feed(vec![1u8, 2]).feed(vec![1]);
+        let (states, matrix) = chain.transition_matrix();
+        assert_eq!(states.len(), 3);
+        assert_eq!(states[0], vec![None]);
+        let one = states
+            .iter()
+            .position(|state| *state == vec![Some(1)])
+            .unwrap();
+        let two = states
+            .iter()
+            .position(|state| *state == vec![Some(2)])
+            .unwrap();
+        assert_eq!(matrix[0][one], 1.0);
+        assert_eq!(matrix[one][two], 0.5);
+        assert_eq!(matrix[one][0], 0.5);
+        assert_eq!(matrix[two][0], 1.0);
+        for row in &matrix {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
     }
 
     #[test]
-    fn feed_str() {
+    fn absorbing_states() {
         let mut chain = Chain::new();
-        chain.feed_str("I like cats and dogs");
+        assert!(chain.absorbing_states().is_empty());
+        chain
+            .feed(vec![1u8, 2, 3, 2, 4])
+            .feed(vec![5, 5])
+            .feed_tagged(0, vec![3]);
+        assert!(chain.absorbing_states().is_empty());
+        chain.map.get_mut(&vec![Some(4)]).unwrap().clear();
+        chain.map.get_mut(&vec![Some(5)]).unwrap().remove(&None);
+        chain.map.get_mut(&vec![Some(3)]).unwrap().remove(&None);
+        let mut absorbing = chain.absorbing_states();
+        absorbing.sort();
+        assert_eq!(absorbing, vec![vec![Some(5)]]);
+        chain.map.get_mut(&vec![Some(2)]).unwrap().remove(&Some(4));
+        let mut absorbing = chain.absorbing_states();
+        absorbing.sort();
+        assert_eq!(absorbing, vec![vec![Some(2)], vec![Some(3)], vec![Some(5)]]);
+        let mut chain = Chain::new();
+        chain.feed_skipgram([1u8, 2, 1, 2], 1);
+        assert_eq!(chain.absorbing_states(), vec![vec![Some(2)]]);
+        assert!(chain.generate().iter().all(|&token| token == 2));
+        chain.set_deadend_policy(DeadEndPolicy::RestartFromStart);
+        assert!(chain.generate().iter().all(|&token| token == 2));
     }
 
     #[test]
-    fn generate_str() {
+    fn termination_probability() {
         let mut chain = Chain::new();
-        chain.feed_str("I like cats").feed_str("I hate cats");
-        assert!(["I like cats", "I hate cats"].contains(&&chain.generate_str()[..]));
+        assert_eq!(chain.termination_probability(5), 0.0);
+        chain.feed([1u8]);
+        assert_eq!(chain.termination_probability(0), 0.0);
+        assert_eq!(chain.termination_probability(1), 0.0);
+        assert_eq!(chain.termination_probability(2), 1.0);
+        chain.feed([1u8, 1]);
+        assert_eq!(chain.termination_probability(2), 2.0 / 3.0);
+        assert!((chain.termination_probability(3) - 8.0 / 9.0).abs() < 1e-12);
+        assert!(chain.termination_probability(100) > 0.999);
     }
 
     #[test]
-    fn generate_str_from_token() {
+    fn predictability() {
         let mut chain = Chain::new();
-        chain.feed_str("I like cats").feed_str("cats are cute");
-        assert!(["cats", "cats are cute"].contains(&&chain.generate_str_from_token("cats")[..]));
+        assert_eq!(chain.predictability(), 1.0);
+        chain.feed(vec![1u8, 2, 3]);
+        assert_eq!(chain.predictability(), 1.0);
+        chain.feed(vec![1, 3]);
+        // [1] is visited 2 out of 7 counts, uniformly between its two successors.
+        assert!((chain.predictability() - 5.0 / 7.0).abs() < 1e-9);
     }
 
     #[test]
-    fn generate_str_from_token_higher_order() {
-        let mut chain = Chain::of_order(2);
-        chain.feed_str("I like cats").feed_str("cats are cute");
-        println!("{:?}", chain.generate_str_from_token("cats"));
-        assert!(["cats", "cats are cute"].contains(&&chain.generate_str_from_token("cats")[..]));
+    fn shortest_path() {
+        let mut chain = Chain::new();
+        chain
+            .feed(vec![1u8, 2, 3, 4, 5])
+            .feed(vec![2, 6, 5])
+            .feed(vec![7, 1]);
+        assert_eq!(chain.shortest_path(&1, &5), Some(vec![1, 2, 6, 5]));
+        assert_eq!(chain.shortest_path(&3, &4), Some(vec![3, 4]));
+        assert_eq!(chain.shortest_path(&2, &2), Some(vec![2]));
+        assert_eq!(chain.shortest_path(&5, &1), None);
+        assert_eq!(chain.shortest_path(&8, &1), None);
     }
 
     #[test]
-    fn generate_str_from_unfound_token() {
+    fn novelty_score() {
         let mut chain = Chain::new();
-        chain.feed_str("I like cats").feed_str("cats are cute");
-        assert_eq!(chain.generate_str_from_token("test"), "");
+        chain.feed([1u8, 2, 3]).feed([4, 2, 5]);
+        assert_eq!(chain.novelty_score(&[1, 2, 3]), 0.0);
+        assert_eq!(chain.novelty_score(&[1, 2, 5]), 0.0);
+        assert_eq!(chain.novelty_score(&[1, 2, 4, 2]), 1.0 / 3.0);
+        assert_eq!(chain.novelty_score(&[3, 1]), 1.0);
+        assert_eq!(chain.novelty_score(&[3]), 0.0);
     }
 
     #[test]
-    fn str_iter() {
+    fn perplexity() {
         let mut chain = Chain::new();
-        chain.feed_str("I like cats and I like dogs");
-        assert_eq!(chain.str_iter().size_hint().1, None);
+        chain.feed(vec![1u8, 2, 3]);
+        assert_eq!(chain.perplexity(&[vec![1, 2, 3]]), 1.0);
+        assert_eq!(chain.perplexity(&[vec![1, 3]]), f64::INFINITY);
     }
 
     #[test]
-    fn str_iter_for() {
+    fn set_unknown_token_policy() {
         let mut chain = Chain::new();
-        chain.feed_str("I like cats and I like dogs");
-        assert_eq!(chain.str_iter_for(5).collect::<Vec<_>>().len(), 5);
+        chain.feed(["a", "b"]).feed(["a", "<unk>"]);
+        assert_eq!(chain.sequence_probability(&["a", "x"]), 0.0);
+        chain.set_unknown_token_policy(UnknownTokenPolicy::UnkState);
+        assert_eq!(chain.sequence_probability(&["a", "x"]), 0.0);
+        chain.reserve_unknown_token("<unk>");
+        assert_eq!(chain.sequence_probability(&["a", "x"]), 0.5);
+        assert_eq!(chain.sequence_probability(&["a", "b"]), 0.5);
+        chain.set_unknown_token_policy(UnknownTokenPolicy::Skip);
+        assert_eq!(chain.sequence_probability(&["a", "x", "b"]), 0.5);
+        assert_eq!(
+            chain.perplexity(&[vec!["a", "x", "b"], vec!["y"]]),
+            chain.perplexity(&[vec!["a", "b"]])
+        );
+        chain.add_alias("b", "bee");
+        assert_eq!(chain.sequence_probability(&["a", "bee"]), 0.5);
+        let mut chain = Chain::new();
+        chain.ignore_case().feed_str("I like Cats");
+        chain.set_unknown_token_policy(UnknownTokenPolicy::Skip);
+        let words = |s: &str| s.split(' ').map(str::to_owned).collect::<Vec<_>>();
+        assert_eq!(chain.sequence_probability(&words("I like Cats")), 1.0);
+        assert_eq!(chain.sequence_probability(&words("I like")), 0.0);
     }
 
     #[test]
-    #[cfg(feature = "yaml")]
-    fn save_then_load() {
-        let mut chain = Chain::of_order(2);
-        chain.feed_str("I like cats and I like dogs");
-        chain.save("test.yaml").unwrap();
-
-        let new_chain = Chain::load("test.yaml").unwrap();
-        assert_eq!(chain, new_chain);
+    fn smoothed_perplexity() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2, 3]).feed(vec![1, 3]);
+        let seen = chain.smoothed_perplexity(&[vec![1, 2, 3]], 0.5);
+        let unseen = chain.smoothed_perplexity(&[vec![3, 2, 1]], 0.5);
+        assert!(seen.is_finite() && unseen.is_finite());
+        assert!(seen < unseen);
+        assert_eq!(chain.smoothed_perplexity(&[], 0.5), 1.0);
     }
 
     #[test]